@@ -0,0 +1,219 @@
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as Tokens};
+use syn::{Data, DeriveInput, Fields, GenericParam, Lifetime, Lit, Meta, NestedMeta};
+
+// #[derive(OxideRow)] on a struct with named fields, one column per field in field order.
+// a field takes #[oxide(...)] with any of key, unique, ordered, text, dictionary,
+// case_insensitive and name = "...".
+// it also writes a struct of typed field handles named after the struct plus Fields
+#[proc_macro_derive(OxideRow, attributes(oxide))]
+pub fn derive_oxide_row(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(t) => t.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+struct Column {
+    ident: syn::Ident,
+    ty: syn::Type,
+    name: String,
+    key: bool,
+    unique: bool,
+    ordered: bool,
+    text: bool,
+    dictionary: bool,
+    case_insensitive: bool,
+}
+
+fn expand(input: &DeriveInput) -> Result<Tokens, syn::Error> {
+    let fields = match input.data {
+        Data::Struct(ref s) => {
+            match s.fields {
+                Fields::Named(ref f) => &f.named,
+                _ => return Err(syn::Error::new_spanned(input, "OxideRow needs named fields")),
+            }
+        }
+        _ => return Err(syn::Error::new_spanned(input, "OxideRow only derives for structs")),
+    };
+    if fields.is_empty() {
+        return Err(syn::Error::new_spanned(input, "OxideRow needs at least one field"));
+    }
+
+    // borrowed string fields tie the row to the bucket's lifetime, so at most one is allowed
+    let mut lifetimes = Vec::new();
+    for p in input.generics.params.iter() {
+        match *p {
+            GenericParam::Lifetime(ref l) => lifetimes.push(l.lifetime.clone()),
+            _ => {
+                return Err(syn::Error::new_spanned(p, "OxideRow does not take type parameters"))
+            }
+        }
+    }
+    if lifetimes.len() > 1 {
+        return Err(syn::Error::new_spanned(&input.generics, "OxideRow takes at most one lifetime"));
+    }
+
+    let mut cols = Vec::new();
+    for f in fields.iter() {
+        cols.push(try!(column(f)));
+    }
+
+    let ident = &input.ident;
+    let vis = &input.vis;
+    // field handles name the row type, so they share its lifetime
+    let (impl_lt, field_lt, ty_generics) = match lifetimes.pop() {
+        Some(l) => {
+            let g = quote!(<#l>);
+            (l.clone(), l, g)
+        }
+        None => {
+            (Lifetime::new("'oxide_row", Span::call_site()),
+             Lifetime::new("'static", Span::call_site()),
+             quote!())
+        }
+    };
+    let fields_ident = syn::Ident::new(&format!("{}Fields", ident), ident.span());
+
+    let handles = cols.iter().map(|c| {
+        let f = &c.ident;
+        let ty = &c.ty;
+        if c.text {
+            quote!(pub #f: ::oxide::TextField<#ident #ty_generics>)
+        } else {
+            quote!(pub #f: ::oxide::Field<#ident #ty_generics,
+                                          <#ty as ::oxide::RowField<#field_lt>>::Inner>)
+        }
+    });
+    let handle_values = cols.iter().enumerate().map(|(i, c)| {
+        let f = &c.ident;
+        if c.text {
+            quote!(#f: ::oxide::__private::text_field(#i))
+        } else {
+            quote!(#f: ::oxide::__private::field(#i))
+        }
+    });
+
+    let specs = cols.iter().map(|c| {
+        let ty = &c.ty;
+        let name = &c.name;
+        let mut spec = quote!(<#ty as ::oxide::RowField<#impl_lt>>::column());
+        if c.text {
+            spec = quote!(::oxide::ColumnSpec { kind: ::oxide::ColumnBuilder::Text, ..#spec });
+        }
+        spec = quote!(#spec.named(#name));
+        if c.key {
+            spec = quote!(#spec.key());
+        }
+        if c.unique {
+            spec = quote!(#spec.unique());
+        }
+        if c.ordered {
+            spec = quote!(#spec.ordered());
+        }
+        if c.dictionary {
+            spec = quote!(#spec.dictionary());
+        }
+        if c.case_insensitive {
+            spec = quote!(#spec.case_insensitive());
+        }
+        quote!(.add_column(#spec))
+    });
+
+    let into = cols.iter().map(|c| {
+        let f = &c.ident;
+        quote!(::oxide::RowField::into_value(self.#f))
+    });
+
+    let n = cols.len();
+    let from = cols.iter().enumerate().map(|(i, c)| {
+        let f = &c.ident;
+        let ty = &c.ty;
+        quote! {
+            #f: match <#ty as ::oxide::RowField<#impl_lt>>::from_value(&row[#i]) {
+                Some(v) => v,
+                None => return Err(::oxide::Error::WrongValueType(#i)),
+            }
+        }
+    });
+
+    Ok(quote! {
+        #[allow(dead_code)]
+        #vis struct #fields_ident #ty_generics {
+            #(#handles),*
+        }
+
+        impl<#impl_lt> ::oxide::OxideRow<#impl_lt> for #ident #ty_generics {
+            type Fields = #fields_ident #ty_generics;
+
+            fn fields() -> #fields_ident #ty_generics {
+                #fields_ident { #(#handle_values),* }
+            }
+
+            fn schema<T: Into<String>>(name: T) -> ::oxide::BucketBuilder {
+                ::oxide::BucketBuilder::new(name) #(#specs)*
+            }
+
+            fn into_values(self) -> Vec<::oxide::Value<#impl_lt>> {
+                vec![#(#into),*]
+            }
+
+            fn from_values(row: &[::oxide::Value<#impl_lt>]) -> Result<Self, ::oxide::Error> {
+                if row.len() != #n {
+                    return Err(::oxide::Error::WrongNumberOfValues(#n, row.len()));
+                }
+                Ok(#ident { #(#from),* })
+            }
+        }
+    })
+}
+
+fn column(f: &syn::Field) -> Result<Column, syn::Error> {
+    let ident = f.ident.clone().unwrap();
+    let mut c = Column {
+        name: ident.to_string(),
+        ident: ident,
+        ty: f.ty.clone(),
+        key: false,
+        unique: false,
+        ordered: false,
+        text: false,
+        dictionary: false,
+        case_insensitive: false,
+    };
+    for attr in f.attrs.iter().filter(|a| a.path.is_ident("oxide")) {
+        let list = match try!(attr.parse_meta()) {
+            Meta::List(l) => l,
+            m => return Err(syn::Error::new_spanned(m, "expected #[oxide(...)]")),
+        };
+        for item in list.nested.iter() {
+            match *item {
+                NestedMeta::Meta(Meta::Path(ref p)) if p.is_ident("key") => c.key = true,
+                NestedMeta::Meta(Meta::Path(ref p)) if p.is_ident("unique") => c.unique = true,
+                NestedMeta::Meta(Meta::Path(ref p)) if p.is_ident("ordered") => c.ordered = true,
+                NestedMeta::Meta(Meta::Path(ref p)) if p.is_ident("text") => c.text = true,
+                NestedMeta::Meta(Meta::Path(ref p)) if p.is_ident("dictionary") => {
+                    c.dictionary = true
+                }
+                NestedMeta::Meta(Meta::Path(ref p)) if p.is_ident("case_insensitive") => {
+                    c.case_insensitive = true
+                }
+                NestedMeta::Meta(Meta::NameValue(ref nv)) if nv.path.is_ident("name") => {
+                    match nv.lit {
+                        Lit::Str(ref s) => c.name = s.value(),
+                        ref l => return Err(syn::Error::new_spanned(l, "expected a string")),
+                    }
+                }
+                ref other => return Err(syn::Error::new_spanned(other, "unknown oxide attribute")),
+            }
+        }
+    }
+    Ok(c)
+}
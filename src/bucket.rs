@@ -1,25 +1,38 @@
+extern crate fnv;
+use fnv::FnvHasher;
+
 extern crate roaring;
 use roaring::RoaringBitmap;
 
+use std::borrow::Cow;
+use std::collections::{Bound, HashMap, HashSet};
 use std::fmt;
+use std::hash::BuildHasherDefault;
+use std::io::{Read, Write};
 use std::ops::Deref;
 use std::slice::IterMut;
-use std::sync::{Mutex, LockResult, MutexGuard};
+use std::sync::Arc;
 
 use errs::Error;
 use token::Token;
-use column::{Column, ColumnBuilder, ColumnRef};
-use value::{Value, ValueStore};
+use column::{Column, ColumnBuilder, ColumnRef, ColumnSpec};
+use value::{Interner, Value, ValueStore, NULL_CODE};
 use matches::{Match, MatchResults};
 use pattern::Pattern;
-use index::{Index, IndexStats};
+use persist::{Reader, Writer};
+use csv::{self, CsvOptions};
+use json;
+use wal::{Wal, RECORD_OPS};
+use index::{FloatKey, Index, IndexStats, KeyPart, Tag};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BucketStats {
     pub columns: usize,
     pub inserts: usize,
     pub deletes: usize,
     pub rows: usize,
+    pub compactions: usize,
+    pub reclaimed: usize,
     pub index_stats: Vec<IndexStats>,
 }
 
@@ -30,6 +43,8 @@ impl fmt::Display for BucketStats {
         try!(writeln!(f, "inserts: {:>}", self.inserts));
         try!(writeln!(f, "deletes: {:>}", self.deletes));
         try!(writeln!(f, "rows   : {:>}", self.rows));
+        try!(writeln!(f, "compactions: {:>}", self.compactions));
+        try!(writeln!(f, "reclaimed  : {:>}", self.reclaimed));
         try!(writeln!(f, "---------------Indices-------------"));
         for i in self.index_stats.iter() {
             try!(write!(f, "{}", i));
@@ -38,54 +53,220 @@ impl fmt::Display for BucketStats {
     }
 }
 
+#[derive(Clone)]
 pub struct Bucket<'b> {
-    write_lock: Mutex<bool>,
     token: Token,
     columns: Vec<Column>,
-    indices: Vec<Index<'b>>,
+    specs: Vec<ColumnSpec>,
+    nullable: Vec<bool>,
+    unique: Vec<bool>,
+    key_cols: Vec<usize>,
+    // the key map, the indices and the value chunks sit behind Arcs, so a copy of the bucket
+    // shares them and copies only what a write touches
+    keys: Arc<KeyIds>,
+    indices: Vec<Arc<Index<'b>>>,
     deleted: RoaringBitmap<usize>,
     values: ValueStore<'b>,
+    auto_compact: Option<f64>,
     stats: BucketStats,
+    // set while a transaction runs, the changes it made so far
+    undo: Option<Vec<Undo<'b>>>,
+}
+
+// what it takes to reverse one change, a rolled back transaction undoes its changes newest first
+#[derive(Clone)]
+enum Undo<'b> {
+    // the row pushed last
+    Insert,
+    // rows that were live until then
+    Delete(Vec<usize>),
+    // the rows and the values they held as (row id, column, value)
+    Update(Vec<usize>, Vec<(usize, usize, Value<'b>)>),
+    // compaction renumbers every row, so everything it touches is kept as it was
+    Compact(Box<(ValueStore<'b>, Vec<Arc<Index<'b>>>, Arc<KeyIds>, RoaringBitmap<usize>)>),
 }
 
+type KeyIds = HashMap<Vec<KeyPart>, usize, BuildHasherDefault<FnvHasher>>;
+
 impl<'b> Bucket<'b> {
-    pub fn new(cols: Vec<ColumnBuilder>) -> Result<Self, Error> {
+    pub fn new(bb: BucketBuilder) -> Result<Self, Error> {
+        let cols = bb.columns;
         let l = cols.len();
         if l == 0 {
             return Err(Error::NoColumn);
         }
-        let col_vec: Vec<Column> = cols.into_iter()
-                                       .map(|cb| {
-                                           match cb {
-                                               ColumnBuilder::UInt => Column::UInt,
-                                               ColumnBuilder::Int => Column::Int,
-                                               ColumnBuilder::Boolean => Column::Boolean,
-                                               ColumnBuilder::Str => Column::Str,
-                                               ColumnBuilder::OwnedStr => Column::OwnedStr,
-                                           }
-                                       })
-                                       .collect();
+        let col_vec: Vec<Column> = cols.iter().map(|cs| cs.kind.column()).collect();
+        let nullable: Vec<bool> = cols.iter().map(|cs| cs.nullable).collect();
+        let unique: Vec<bool> = cols.iter().map(|cs| cs.unique).collect();
+        let coded: Vec<bool> = cols.iter().map(|cs| cs.dictionary).collect();
+        let mut key_cols = Vec::new();
+        let mut names = HashSet::new();
+        for (i, cs) in cols.iter().enumerate() {
+            if let Some(ref name) = cs.name {
+                if !names.insert(name) {
+                    return Err(Error::DuplicateColumnName(name.clone()));
+                }
+            }
+            match col_vec[i] {
+                Column::Text | Column::Tags if cs.unique => {
+                    return Err(Error::InvalidUniqueColumn(i))
+                }
+                Column::OwnedStr => {}
+                _ if cs.dictionary => return Err(Error::InvalidDictionaryColumn(i)),
+                _ => {}
+            }
+            if !cs.key {
+                continue;
+            }
+            match col_vec[i] {
+                Column::Text | Column::Tags => return Err(Error::InvalidKeyColumn(i)),
+                _ if cs.nullable => return Err(Error::InvalidKeyColumn(i)),
+                _ => key_cols.push(i),
+            }
+        }
         let mut b = Bucket {
-            write_lock: Mutex::new(true),
             token: Token::new(),
             columns: col_vec,
+            specs: cols,
+            nullable: nullable,
+            unique: unique,
+            key_cols: key_cols,
+            keys: Arc::new(HashMap::default()),
             indices: Vec::new(),
             deleted: RoaringBitmap::new(),
-            values: ValueStore::new(l),
+            values: ValueStore::new(&coded),
+            auto_compact: bb.auto_compact,
             stats: BucketStats {
                 columns: l,
                 inserts: 0,
                 deletes: 0,
                 rows: 0,
+                compactions: 0,
+                reclaimed: 0,
                 index_stats: Vec::new(),
             },
+            undo: None,
         };
-        for col in &b.columns {
-            b.indices.push(Index::new_by_column(col));
+        for (col, cs) in b.columns.iter().zip(b.specs.iter()) {
+            b.indices.push(Arc::new(Index::new_by_column(col, cs)));
         }
         Ok(b)
     }
 
+    // schema, rows, deleted ids and stats, plus the index bitmaps if asked for
+    pub fn save(&self, w: &mut Writer, with_indices: bool) {
+        save_schema(w, &self.specs, self.auto_compact);
+        w.usize(self.stats.inserts);
+        w.usize(self.stats.deletes);
+        w.usize(self.stats.compactions);
+        w.usize(self.stats.reclaimed);
+        let rows = self.values.rows();
+        w.usize(rows);
+        for id in 0..rows {
+            for v in self.row(id).iter() {
+                w.value(v);
+            }
+        }
+        w.ids(&self.deleted);
+        w.bool(with_indices);
+        if with_indices {
+            for idx in self.indices.iter() {
+                idx.save(w);
+            }
+        }
+    }
+
+    pub fn load(name: &str, r: &mut Reader<'b>) -> Result<Self, Error> {
+        let mut b = try!(Bucket::new(try!(load_schema(name, r))));
+        b.stats.inserts = try!(r.usize());
+        b.stats.deletes = try!(r.usize());
+        b.stats.compactions = try!(r.usize());
+        b.stats.reclaimed = try!(r.usize());
+        let rows = try!(r.usize());
+        let mut all = Vec::with_capacity(rows);
+        for _ in 0..rows {
+            let mut row = Vec::with_capacity(b.columns.len());
+            for _ in 0..b.columns.len() {
+                row.push(try!(r.value()));
+            }
+            if validate_insert_value(&b.columns, &b.nullable, &row).is_err() {
+                return Err(Error::Corrupt("row does not fit the schema"));
+            }
+            all.push(row);
+        }
+        b.deleted = try!(r.ids(rows));
+        if try!(r.bool()) {
+            for idx in b.indices.iter_mut().map(Arc::make_mut) {
+                try!(idx.load(r, rows));
+            }
+        } else {
+            for (id, row) in all.iter().enumerate() {
+                for (idx, v) in b.indices.iter_mut().map(Arc::make_mut).zip(row.iter()) {
+                    idx.insert(v, id);
+                }
+            }
+        }
+        // rows are stored once the indices are filled, dictionary columns hold their codes
+        for row in all.into_iter() {
+            let codes = b.codes(&row);
+            for (v, c) in row.iter().zip(codes.iter()) {
+                if v.as_str().is_some() && *c == Some(NULL_CODE) {
+                    return Err(Error::Corrupt("string missing from its dictionary"));
+                }
+            }
+            try!(b.values.insert(row, &codes));
+        }
+        // rows breaking the key or unique constraints would keep breaking them on every write
+        for id in b.live_ids().iter() {
+            if !b.key_cols.is_empty() {
+                let k = b.stored_key(id);
+                if Arc::make_mut(&mut b.keys).insert(k, id).is_some() {
+                    return Err(Error::Corrupt("duplicate key"));
+                }
+            }
+            for col in 0..b.columns.len() {
+                if !b.unique[col] {
+                    continue;
+                }
+                let v = b.value(id, col);
+                if let Value::Null = *v {
+                    continue;
+                }
+                if b.unique_holder(col, &v) != Some(id) {
+                    return Err(Error::Corrupt("duplicate value in a unique column"));
+                }
+            }
+        }
+        Ok(b)
+    }
+
+    // the bucket with every borrowed string turned into a shared one, strs keeps a string
+    // that is in many rows down to one copy
+    pub fn into_owned(self, strs: &mut Interner<'b>) -> Bucket<'static> {
+        let values = self.values.into_owned(strs);
+        Bucket {
+            token: self.token,
+            columns: self.columns,
+            specs: self.specs,
+            nullable: self.nullable,
+            unique: self.unique,
+            key_cols: self.key_cols,
+            keys: self.keys,
+            indices: self.indices
+                .into_iter()
+                .map(|idx| {
+                    let idx = Arc::try_unwrap(idx).unwrap_or_else(|idx| (*idx).clone());
+                    Arc::new(idx.into_owned(strs))
+                })
+                .collect(),
+            deleted: self.deleted,
+            values: values,
+            auto_compact: self.auto_compact,
+            stats: self.stats,
+            undo: None,
+        }
+    }
+
     pub fn rows(&self) -> usize {
         let all: RoaringBitmap<usize> = (0..self.values.rows()).collect();
         (all ^ &self.deleted).len()
@@ -101,15 +282,15 @@ impl<'b> Bucket<'b> {
             inserts: self.stats.inserts,
             deletes: self.stats.deletes,
             rows: self.stats.inserts - self.stats.deletes,
+            compactions: self.stats.compactions,
+            reclaimed: self.stats.reclaimed,
             index_stats: is,
         }
     }
 
-    pub fn write(&mut self) -> LockResult<MutexGuard<bool>> {
-        self.write_lock.lock()
-    }
-
-    pub fn find<'a>(&self, matches: &[Match<'a>]) -> Result<Option<MatchResults>, Error> {
+    pub fn find<'s, 'a>(&'s self,
+                        matches: &[Match<'a>])
+                        -> Result<Option<MatchResults<'s, 'b>>, Error> {
         let found = try!(self.find_id(matches));
         if let Some(ref ids) = found {
             Ok(Some(self.get_by_ids(ids)))
@@ -130,7 +311,31 @@ impl<'b> Bucket<'b> {
         }
     }
 
-    pub fn find_pattern<'c>(&self, pattern: &Pattern<'c>) -> Result<Option<MatchResults>, Error> {
+    pub fn get_column_ref_by_name(&self, name: &str) -> Option<ColumnRef> {
+        self.column_by_name(name).and_then(|i| self.get_column_ref(i))
+    }
+
+    // the columns in order, with their names, types and flags
+    pub fn schema(&self) -> &[ColumnSpec] {
+        &self.specs
+    }
+
+    // the positional matches find, delete and update take, columns left out match anything
+    pub fn matches_by_name<'a>(&self, named: &[(&str, Match<'a>)]) -> Result<Vec<Match<'a>>, Error> {
+        let slots = try!(self.slots_by_name(named));
+        Ok(slots.into_iter().map(|m| m.unwrap_or(Match::Any)).collect())
+    }
+
+    pub fn find_named<'s, 'a>(&'s self,
+                              named: &[(&str, Match<'a>)])
+                              -> Result<Option<MatchResults<'s, 'b>>, Error> {
+        let ms = try!(self.matches_by_name(named));
+        self.find(&ms)
+    }
+
+    pub fn find_pattern<'s, 'c>(&'s self,
+                                pattern: &Pattern<'c>)
+                                -> Result<Option<MatchResults<'s, 'b>>, Error> {
         match self.find_pattern_internal(pattern) {
             Ok(Some(ref ids)) => Ok(Some(self.get_by_ids(ids))),
             Err(e) => Err(e),
@@ -138,75 +343,547 @@ impl<'b> Bucket<'b> {
         }
     }
 
-    fn insert(&mut self, vals: Vec<Value<'b>>) -> Result<(), Error> {
-        try!(validate_insert_value(&self.columns, &vals));
-        try!(self.values.insert(&vals));
-        self.stats.inserts += 1;
-        let cur_id = self.values.next_id() - 1;
-        for index_and_val in self.indices.iter_mut().zip(vals.iter()) {
-            let (i, v) = index_and_val;
-            i.insert(v, cur_id);
+    // writes the rows matching pattern, or every live row, and returns how many were written
+    pub fn export_csv<'c, W: Write>(&self,
+                                    mut w: W,
+                                    opts: &CsvOptions,
+                                    pattern: Option<&Pattern<'c>>)
+                                    -> Result<usize, Error> {
+        try!(opts.check());
+        let ids: Vec<usize> = match pattern {
+            Some(p) => try!(self.find_pattern_internal(p)).unwrap_or(Vec::new()),
+            None => self.live_ids().iter().collect(),
+        };
+        if opts.headers {
+            let mut names = Vec::new();
+            for i in 0..self.columns.len() {
+                let text = match opts.columns.iter().find(|&&(_, c)| c == i) {
+                    Some(&(ref name, _)) => name.clone(),
+                    None => self.column_name(i),
+                };
+                names.push(csv::Field {
+                    text: text,
+                    quoted: false,
+                });
+            }
+            try!(csv::write_record(&mut w, &names, opts));
         }
-        Ok(())
+        for &id in ids.iter() {
+            let fields: Vec<csv::Field> = self.row(id)
+                                              .iter()
+                                              .map(|v| csv::format_value(v, opts))
+                                              .collect();
+            try!(csv::write_record(&mut w, &fields, opts));
+        }
+        Ok(ids.len())
     }
 
-    fn insert_unique(&mut self, vals: Vec<Value<'b>>) -> Result<bool, Error> {
-        try!(validate_insert_value(&self.columns, &vals));
-        let ms: Vec<Match> = vals.iter()
-                                 .map(|v| {
-                                     match *v {
-                                         Value::UInt(u) => Match::UInt(u),
-                                         Value::Int(i) => Match::Int(i),
-                                         Value::Boolean(b) => Match::Boolean(b),
-                                         Value::Str(s) => Match::Str(s),
-                                         Value::OwnedStr(ref s) => Match::OwnedStr(s.clone()),
-                                     }
-                                 })
-                                 .collect();
-        if let Ok(Some(_)) = self.find(&ms) {
+    // a json object for one row of this bucket's MatchResults, members keyed by column name
+    pub fn row_json(&self, row: &[Value]) -> String {
+        let names = self.column_names();
+        let mut out = String::new();
+        json::write_row(&mut out, &names, row);
+        out
+    }
+
+    // a json array with an object per row
+    pub fn results_json(&self, results: &MatchResults) -> String {
+        let names = self.column_names();
+        let mut out = String::from("[");
+        for (i, row) in results.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            json::write_row(&mut out, &names, row);
+        }
+        out.push(']');
+        out
+    }
+
+    // writes the rows matching pattern, or every live row, one json object per line
+    pub fn export_ndjson<'c, W: Write>(&self,
+                                       mut w: W,
+                                       pattern: Option<&Pattern<'c>>)
+                                       -> Result<usize, Error> {
+        let ids: Vec<usize> = match pattern {
+            Some(p) => try!(self.find_pattern_internal(p)).unwrap_or(Vec::new()),
+            None => self.live_ids().iter().collect(),
+        };
+        let names = self.column_names();
+        for &id in ids.iter() {
+            let mut line = String::new();
+            json::write_row(&mut line, &names, &self.row(id));
+            line.push('\n');
+            try!(w.write_all(line.as_bytes()));
+        }
+        Ok(ids.len())
+    }
+
+    // the live row with this primary key, one value per key column in column order
+    pub fn get_by_key<'s>(&'s self, key: &[Value]) -> Result<Option<Cow<'s, [Value<'b>]>>, Error> {
+        if self.key_cols.is_empty() {
+            return Err(Error::NoKey);
+        }
+        if self.key_cols.len() != key.len() {
+            return Err(Error::WrongNumberOfValues(self.key_cols.len(), key.len()));
+        }
+        for (&col, v) in self.key_cols.iter().zip(key.iter()) {
+            try!(validate_value(&self.columns[col], false, col, v));
+        }
+        let k: Vec<KeyPart> = key.iter().map(KeyPart::new).collect();
+        match self.keys.get(&k) {
+            Some(&id) => Ok(Some(self.row(id))),
+            None => Ok(None),
+        }
+    }
+
+    fn insert(&mut self, mut vals: Vec<Value<'b>>) -> Result<(), Error> {
+        try!(validate_insert_value(&self.columns, &self.nullable, &vals));
+        dedup_tags(&mut vals);
+        try!(self.check_key(&vals));
+        try!(self.check_unique(&vals));
+        self.push_row(vals)
+    }
+
+    fn insert_unique(&mut self, mut vals: Vec<Value<'b>>) -> Result<bool, Error> {
+        try!(validate_insert_value(&self.columns, &self.nullable, &vals));
+        dedup_tags(&mut vals);
+        if let Some(_) = self.find_row(&vals) {
             return Ok(false);
         }
+        try!(self.check_key(&vals));
+        try!(self.check_unique(&vals));
+        try!(self.push_row(vals));
+        Ok(true)
+    }
 
-        try!(self.values.insert(&vals));
-        self.stats.inserts += 1;
-        let cur_id = self.values.next_id() - 1;
-        for index_and_val in self.indices.iter_mut().zip(vals.iter()) {
-            let (i, v) = index_and_val;
+    // inserts the row, or overwrites the row with the same key in place, true if it was inserted
+    fn upsert(&mut self, mut vals: Vec<Value<'b>>) -> Result<bool, Error> {
+        if self.key_cols.is_empty() {
+            return Err(Error::NoKey);
+        }
+        try!(validate_insert_value(&self.columns, &self.nullable, &vals));
+        dedup_tags(&mut vals);
+        let existing = self.keys.get(&self.row_key(&vals)).cloned();
+        match existing {
+            Some(id) => {
+                let assignments: Vec<(usize, Value<'b>)> = vals.into_iter().enumerate().collect();
+                try!(self.check_unique_update(&[id], &assignments));
+                self.update_by_ids(&[id], assignments);
+                Ok(false)
+            }
+            None => {
+                try!(self.check_unique(&vals));
+                try!(self.push_row(vals));
+                Ok(true)
+            }
+        }
+    }
+
+    // what csv headers call column i, an unnamed column goes by its index
+    fn column_name(&self, i: usize) -> String {
+        match self.specs[i].name {
+            Some(ref name) => name.clone(),
+            None => i.to_string(),
+        }
+    }
+
+    // the column a csv header names, the other way round
+    fn column_by_header(&self, header: &str) -> Option<usize> {
+        (0..self.columns.len()).find(|&i| self.column_name(i) == header)
+    }
+
+    fn column_names(&self) -> Vec<Option<String>> {
+        self.specs.iter().map(|cs| cs.name.clone()).collect()
+    }
+
+    fn column_by_name(&self, name: &str) -> Option<usize> {
+        self.specs.iter().position(|cs| cs.name.as_ref().map_or(false, |n| n == name))
+    }
+
+    // one slot per column for entries keyed by column name
+    fn slots_by_name<T: Clone>(&self, named: &[(&str, T)]) -> Result<Vec<Option<T>>, Error> {
+        let mut slots = vec![None; self.columns.len()];
+        for &(name, ref v) in named.iter() {
+            let i = match self.column_by_name(name) {
+                Some(i) => i,
+                None => return Err(Error::NoColumnNamed(name.to_owned())),
+            };
+            if slots[i].is_some() {
+                return Err(Error::RepeatedColumn(name.to_owned()));
+            }
+            slots[i] = Some(v.clone());
+        }
+        Ok(slots)
+    }
+
+    fn row_key(&self, vals: &[Value]) -> Vec<KeyPart> {
+        self.key_cols.iter().map(|&c| KeyPart::new(&vals[c])).collect()
+    }
+
+    fn stored_key(&self, id: usize) -> Vec<KeyPart> {
+        self.key_cols.iter().map(|&c| KeyPart::new(&self.value(id, c))).collect()
+    }
+
+    // a row as it was inserted, a dictionary column's code comes back as its string. only a
+    // bucket with dictionary columns has to put the row together
+    fn row<'s>(&'s self, id: usize) -> Cow<'s, [Value<'b>]> {
+        if !self.values.has_codes() {
+            return Cow::Borrowed(self.values.values(id));
+        }
+        Cow::Owned((0..self.columns.len()).map(|col| self.value(id, col).into_owned()).collect())
+    }
+
+    // what the row holds in each column, see Index::code
+    fn codes(&self, vals: &[Value]) -> Vec<Option<u32>> {
+        self.indices.iter().zip(vals.iter()).map(|(idx, v)| idx.code(v)).collect()
+    }
+
+    fn value<'s>(&'s self, id: usize, col: usize) -> Cow<'s, Value<'b>> {
+        match self.values.code(id, col) {
+            Some(c) => Cow::Owned(self.indices[col].decode(c)),
+            None => Cow::Borrowed(self.values.get(id, col)),
+        }
+    }
+
+    // puts v in the cell, the row moves from the old value's bitmap to v's. returns the old value
+    fn replace(&mut self, id: usize, col: usize, v: Value<'b>) -> Value<'b> {
+        // a code is turned into its string before the dictionary may let go of it
+        let coded = self.values.code(id, col).map(|c| self.indices[col].decode(c));
+        let code = {
+            let idx = Arc::make_mut(&mut self.indices[col]);
+            match coded {
+                Some(ref old) => idx.remove(old, id),
+                None => idx.remove(self.values.get(id, col), id),
+            }
+            idx.insert(&v, id);
+            idx.code(&v)
+        };
+        let old = self.values.set(id, col, v, code);
+        coded.unwrap_or(old)
+    }
+
+    fn key_string(&self, vals: &[Value]) -> String {
+        let parts: Vec<String> = self.key_cols.iter().map(|&c| format!("{}", vals[c])).collect();
+        parts.join(", ")
+    }
+
+    fn check_key(&self, vals: &[Value]) -> Result<(), Error> {
+        if !self.key_cols.is_empty() && self.keys.contains_key(&self.row_key(vals)) {
+            return Err(Error::DuplicateKey(self.key_string(vals)));
+        }
+        Ok(())
+    }
+
+    // the rows being updated may swap keys among themselves but not take one held elsewhere
+    fn check_key_update(&self, ids: &[usize], assignments: &[(usize, Value)]) -> Result<(), Error> {
+        if !assignments.iter().any(|&(col, _)| self.key_cols.contains(&col)) {
+            return Ok(());
+        }
+        let updated: HashSet<usize> = ids.iter().cloned().collect();
+        let mut seen = HashSet::new();
+        for &id in ids.iter() {
+            let mut row = self.row(id).into_owned();
+            for &(col, ref v) in assignments.iter() {
+                row[col] = v.clone();
+            }
+            let k = self.row_key(&row);
+            let taken = match self.keys.get(&k) {
+                Some(other) => !updated.contains(other),
+                None => false,
+            };
+            if taken || !seen.insert(k) {
+                return Err(Error::DuplicateKey(self.key_string(&row)));
+            }
+        }
+        Ok(())
+    }
+
+    // a live row already holding v in a unique column
+    fn unique_holder(&self, col: usize, v: &Value) -> Option<usize> {
+        if let Value::Null = *v {
+            return None;
+        }
+        match self.indices[col].get_value_index(v) {
+            Some(b) => b.iter().find(|id| !self.deleted.contains(*id)),
+            None => None,
+        }
+    }
+
+    fn check_unique(&self, vals: &[Value]) -> Result<(), Error> {
+        for (col, v) in vals.iter().enumerate() {
+            if self.unique[col] && self.unique_holder(col, v).is_some() {
+                return Err(Error::UniqueViolation {
+                    column: col,
+                    value: format!("{}", v),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // setting a unique column is fine for a single row, as long as no other row holds the value
+    fn check_unique_update(&self, ids: &[usize], assignments: &[(usize, Value)]) -> Result<(), Error> {
+        for &(col, ref v) in assignments.iter() {
+            if !self.unique[col] {
+                continue;
+            }
+            if let Value::Null = *v {
+                continue;
+            }
+            let clash = match self.unique_holder(col, v) {
+                Some(id) => ids.len() > 1 || id != ids[0],
+                None => ids.len() > 1,
+            };
+            if clash {
+                return Err(Error::UniqueViolation {
+                    column: col,
+                    value: format!("{}", v),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // the assignments are validated already
+    fn update_ids(&mut self,
+                  ids: &[usize],
+                  assignments: Vec<(usize, Value<'b>)>)
+                  -> Result<usize, Error> {
+        try!(self.check_key_update(ids, &assignments));
+        try!(self.check_unique_update(ids, &assignments));
+        Ok(self.update_by_ids(ids, assignments))
+    }
+
+    fn validate_assignments(&self, assignments: &[(usize, Value<'b>)]) -> Result<(), Error> {
+        for &(col, ref v) in assignments.iter() {
+            if col >= self.columns.len() {
+                return Err(Error::NoSuchColumn(col));
+            }
+            try!(validate_value(&self.columns[col], self.nullable[col], col, v));
+        }
+        Ok(())
+    }
+
+    // rewrites the values in place, each row id moves from the old value's bitmap to the new one's
+    fn update_by_ids(&mut self, ids: &[usize], mut assignments: Vec<(usize, Value<'b>)>) -> usize {
+        for &mut (_, ref mut v) in assignments.iter_mut() {
+            dedup_tag_value(v);
+        }
+        let rekey = assignments.iter().any(|&(col, _)| self.key_cols.contains(&col));
+        if rekey {
+            for &id in ids.iter() {
+                let k = self.stored_key(id);
+                Arc::make_mut(&mut self.keys).remove(&k);
+            }
+        }
+        let mut old = Vec::new();
+        for &id in ids.iter() {
+            for &(col, ref v) in assignments.iter() {
+                let was = self.replace(id, col, v.clone());
+                if self.undo.is_some() {
+                    old.push((id, col, was));
+                }
+            }
+        }
+        if self.undo.is_some() {
+            self.record(Undo::Update(ids.to_vec(), old));
+        }
+        if rekey {
+            for &id in ids.iter() {
+                let k = self.stored_key(id);
+                Arc::make_mut(&mut self.keys).insert(k, id);
+            }
+        }
+        ids.len()
+    }
+
+    fn push_row(&mut self, vals: Vec<Value<'b>>) -> Result<(), Error> {
+        let cur_id = self.values.next_id();
+        for (i, v) in self.indices.iter_mut().map(Arc::make_mut).zip(vals.iter()) {
             i.insert(v, cur_id);
         }
-        Ok(true)
+        let codes = self.codes(&vals);
+        if !self.key_cols.is_empty() {
+            let k = self.row_key(&vals);
+            Arc::make_mut(&mut self.keys).insert(k, cur_id);
+        }
+        try!(self.values.insert(vals, &codes));
+        self.record(Undo::Insert);
+        self.stats.inserts += 1;
+        Ok(())
+    }
+
+    // id of a live row holding exactly these values
+    fn find_row(&self, vals: &[Value]) -> Option<usize> {
+        let ms: Vec<Match> = self.columns
+                                 .iter()
+                                 .zip(vals.iter())
+                                 .map(|(c, v)| exact_match(c, v))
+                                 .collect();
+        let candidates: Vec<usize> = match self.find_id(&ms) {
+            Ok(Some(ids)) => ids,
+            Ok(None) => return None,
+            // only text columns, nothing the indices can narrow down
+            Err(_) => self.live_ids().iter().collect(),
+        };
+        candidates.into_iter().find(|&id| {
+            self.row(id)
+                .iter()
+                .zip(vals.iter())
+                .all(|(l, r)| same_value(l, r))
+        })
     }
 
-    fn get_by_ids(&self, ids: &[usize]) -> MatchResults {
-        let mut out: Vec<&[Value]> = Vec::new();
-        let w = self.values.width();
-        // println!("row width is {}", w);
+    // every live row, in row id order
+    pub fn all_rows<'s>(&'s self) -> MatchResults<'s, 'b> {
+        let ids: Vec<usize> = self.live_ids().iter().collect();
+        self.get_by_ids(&ids)
+    }
+
+    fn get_by_ids<'s>(&'s self, ids: &[usize]) -> MatchResults<'s, 'b> {
+        let mut out = Vec::new();
         for id in ids.iter() {
-            // println!("id {} is in the result", id);
-            out.push(self.values.slice_at(id * w, id * w + w));
+            out.push(self.row(*id));
         }
         MatchResults::new(out)
     }
 
     fn delete_by_ids(&mut self, ids: &[usize]) -> usize {
+        if self.undo.is_some() {
+            let live = ids.iter().cloned().filter(|&id| !self.deleted.contains(id)).collect();
+            self.record(Undo::Delete(live));
+        }
         let mut c = 0_usize;
         for id in ids.iter() {
             self.deleted.insert(*id);
+            if !self.key_cols.is_empty() {
+                let k = self.stored_key(*id);
+                Arc::make_mut(&mut self.keys).remove(&k);
+            }
             c += 1;
         }
         self.stats.deletes += c;
+        if let Some(ratio) = self.auto_compact {
+            if self.deleted.len() as f64 >= ratio * self.values.rows() as f64 {
+                self.compact();
+            }
+        }
         c
     }
 
+    // drops deleted rows from the value store and renumbers the survivors in every index
+    fn compact(&mut self) -> usize {
+        let reclaimed = self.deleted.len();
+        if reclaimed == 0 {
+            return 0;
+        }
+        if self.undo.is_some() {
+            let before = (self.values.clone(),
+                          self.indices.clone(),
+                          self.keys.clone(),
+                          self.deleted.clone());
+            self.record(Undo::Compact(Box::new(before)));
+        }
+        let mut keep = Vec::with_capacity(self.values.rows());
+        let mut ids = Vec::with_capacity(self.values.rows());
+        let mut next = 0;
+        for id in 0..self.values.rows() {
+            if self.deleted.contains(id) {
+                keep.push(false);
+                ids.push(None);
+            } else {
+                keep.push(true);
+                ids.push(Some(next));
+                next += 1;
+            }
+        }
+        self.values.retain(&keep);
+        for idx in self.indices.iter_mut().map(Arc::make_mut) {
+            idx.remap(&ids);
+        }
+        for id in Arc::make_mut(&mut self.keys).values_mut() {
+            *id = ids[*id].unwrap();
+        }
+        self.deleted = RoaringBitmap::new();
+        self.stats.compactions += 1;
+        self.stats.reclaimed += reclaimed;
+        reclaimed
+    }
+
+    fn record(&mut self, u: Undo<'b>) {
+        if let Some(ref mut undo) = self.undo {
+            undo.push(u);
+        }
+    }
+
+    // undoes every change recorded after the first `mark`
+    fn rollback(&mut self, mark: usize) {
+        let undo = match self.undo {
+            Some(ref mut undo) => undo.split_off(mark),
+            None => return,
+        };
+        for u in undo.into_iter().rev() {
+            match u {
+                Undo::Insert => {
+                    let id = self.values.rows() - 1;
+                    if !self.key_cols.is_empty() {
+                        let k = self.stored_key(id);
+                        Arc::make_mut(&mut self.keys).remove(&k);
+                    }
+                    let vals = self.row(id).into_owned();
+                    for (idx, v) in self.indices.iter_mut().map(Arc::make_mut).zip(vals.iter()) {
+                        idx.remove(v, id);
+                    }
+                    self.values.pop();
+                }
+                Undo::Delete(ids) => {
+                    for &id in ids.iter() {
+                        self.deleted.remove(id);
+                        if !self.key_cols.is_empty() {
+                            let k = self.stored_key(id);
+                            Arc::make_mut(&mut self.keys).insert(k, id);
+                        }
+                    }
+                }
+                Undo::Update(ids, old) => {
+                    if !self.key_cols.is_empty() {
+                        for &id in ids.iter() {
+                            let k = self.stored_key(id);
+                            Arc::make_mut(&mut self.keys).remove(&k);
+                        }
+                    }
+                    for (id, col, v) in old.into_iter().rev() {
+                        self.replace(id, col, v);
+                    }
+                    if !self.key_cols.is_empty() {
+                        for &id in ids.iter() {
+                            if !self.deleted.contains(id) {
+                                let k = self.stored_key(id);
+                                Arc::make_mut(&mut self.keys).insert(k, id);
+                            }
+                        }
+                    }
+                }
+                Undo::Compact(before) => {
+                    let (values, indices, keys, deleted) = *before;
+                    self.values = values;
+                    self.indices = indices;
+                    self.keys = keys;
+                    self.deleted = deleted;
+                }
+            }
+        }
+    }
+
     fn find_id<'a>(&self, matches: &[Match<'a>]) -> Result<Option<Vec<usize>>, Error> {
         try!(validate_find_simple_pattern(&self.columns, matches));
-        let mut indices_to_match: Vec<&RoaringBitmap<usize>> = Vec::new();
-        for index_and_match in self.indices.iter().zip(matches.iter()) {
-            let (idx, match_) = index_and_match;
+        let mut indices_to_match: Vec<Cow<RoaringBitmap<usize>>> = Vec::new();
+        for (col_id, match_) in matches.iter().enumerate() {
             if let &Match::Any = match_ {
                 continue;
             }
-            if let Some(t) = idx.get_match_index(match_) {
+            if let Some(t) = self.match_index(col_id, match_) {
                 indices_to_match.push(t);
             } else {
                 return Ok(None);
@@ -223,10 +900,10 @@ impl<'b> Bucket<'b> {
         //     println!("index has length {}", i.len());
         // }
 
-        let init = indices_to_match[0].clone();
+        let init = indices_to_match[0].clone().into_owned();
         let matches: RoaringBitmap<usize> = indices_to_match.iter()
                                                             .skip(1)
-                                                            .fold(init, |acc, &i| acc & i);
+                                                            .fold(init, |acc, i| acc & &**i);
         // println!("out length {}", matches.len());
         if matches.len() == 0 {
             return Ok(None);
@@ -248,12 +925,42 @@ impl<'b> Bucket<'b> {
         }
     }
 
-    fn delete<'a>(&mut self, matches: &[Match<'a>]) -> Result<usize, Error> {
-        if let Ok(Some(ref ids)) = self.find_id(matches) {
-            Ok(self.delete_by_ids(ids))
-        } else {
-            Ok(0)
+    // applies one op a WriteHandle logged. ops fail on replay exactly as they failed when they
+    // were logged, so only a malformed op is an error
+    pub fn replay(&mut self, r: &mut Reader<'b>) -> Result<(), Error> {
+        match try!(r.u8()) {
+            OP_INSERT => {
+                let _ = self.insert(try!(r.values()));
+            }
+            OP_INSERT_UNIQUE => {
+                let _ = self.insert_unique(try!(r.values()));
+            }
+            OP_UPSERT => {
+                let _ = self.upsert(try!(r.values()));
+            }
+            OP_DELETE => {
+                let ids: Vec<usize> = try!(r.ids(self.values.rows())).iter().collect();
+                self.delete_by_ids(&ids);
+            }
+            OP_UPDATE => {
+                let ids: Vec<usize> = try!(r.ids(self.values.rows())).iter().collect();
+                let n = try!(r.usize());
+                let mut assignments = Vec::with_capacity(n);
+                for _ in 0..n {
+                    let col = try!(r.usize());
+                    assignments.push((col, try!(r.value())));
+                }
+                if self.validate_assignments(&assignments).is_err() {
+                    return Err(Error::Corrupt("invalid update in log"));
+                }
+                let _ = self.update_ids(&ids, assignments);
+            }
+            OP_COMPACT => {
+                self.compact();
+            }
+            _ => return Err(Error::Corrupt("unknown log op")),
         }
+        Ok(())
     }
 
     fn walk_pattern<'a>(&self, pattern: &Pattern<'a>) -> Result<RoaringBitmap<usize>, Error> {
@@ -271,6 +978,23 @@ impl<'b> Bucket<'b> {
                     Ok(RoaringBitmap::new())
                 }
             }
+            Pattern::Match(refcr, ref m) => {
+                let &ColumnRef { id: col_id, t: token, r: ref refcol } = refcr;
+                if self.token != token || col_id >= self.columns.len() {
+                    return Err(Error::InvalidColumnRef);
+                }
+                if let Match::Any = *m {
+                    return Ok((0..self.values.rows()).collect());
+                }
+                if !match_simple_type_eq(refcol, m) {
+                    return Err(Error::InvalidColumnMatch);
+                }
+                if let Some(b) = self.match_index(col_id, m) {
+                    Ok(b.into_owned())
+                } else {
+                    Ok(RoaringBitmap::new())
+                }
+            }
             Pattern::And(ref left, ref right) => {
                 match (self.walk_pattern(left), self.walk_pattern(right)) {
                     (Ok(bl), Ok(br)) => Ok(bl & br),
@@ -285,6 +1009,21 @@ impl<'b> Bucket<'b> {
                     (_, Err(e)) => Err(e),
                 }
             }
+            // like Match::Not, a negated match on one column leaves out the rows where that
+            // column is null, save for NotNull whose negation is the null rows. a negated And
+            // or Or takes every row the inner pattern does not, nulls included
+            Pattern::Not(ref inner) => {
+                let b = try!(self.walk_pattern(inner));
+                let mut out = self.live_ids() - b;
+                match **inner {
+                    Pattern::Match(_, Match::NotNull) => {}
+                    Pattern::Single(r, _) | Pattern::Match(r, _) => {
+                        out.difference_with(self.indices[r.id].nulls())
+                    }
+                    _ => {}
+                }
+                Ok(out)
+            }
         }
     }
 
@@ -309,11 +1048,183 @@ impl<'b> Bucket<'b> {
         }
     }
 
-    fn index_iter_mut<'c>(&'c mut self) -> IterMut<'c, Index<'b>> {
+    // bitmap of the rows in one column satisfying a match, deleted rows included
+    fn match_index<'a>(&self, col_id: usize, m: &Match<'a>) -> Option<Cow<RoaringBitmap<usize>>> {
+        let idx = &self.indices[col_id];
+        match *m {
+            Match::NotNull => {
+                let all: RoaringBitmap<usize> = (0..self.values.rows()).collect();
+                let b = all - idx.nulls();
+                if b.len() == 0 {
+                    None
+                } else {
+                    Some(Cow::Owned(b))
+                }
+            }
+            // like sql, a null is neither equal nor unequal to anything
+            Match::Not(ref v) => {
+                let mut b = self.live_ids() - idx.nulls();
+                if let Some(eq) = idx.get_value_index(v) {
+                    b.difference_with(eq);
+                }
+                if b.len() == 0 {
+                    None
+                } else {
+                    Some(Cow::Owned(b))
+                }
+            }
+            // every row has all of no tags, the same as Any
+            Match::HasAll(ref ts) if ts.is_empty() => {
+                let b = self.live_ids();
+                if b.len() == 0 {
+                    None
+                } else {
+                    Some(Cow::Owned(b))
+                }
+            }
+            _ => idx.get_match_index(m),
+        }
+    }
+
+    fn live_ids(&self) -> RoaringBitmap<usize> {
+        let all: RoaringBitmap<usize> = (0..self.values.rows()).collect();
+        all - &self.deleted
+    }
+
+    fn index_iter_mut<'c>(&'c mut self) -> IterMut<'c, Arc<Index<'b>>> {
         self.indices.iter_mut()
     }
 }
 
+pub fn save_schema(w: &mut Writer, specs: &[ColumnSpec], auto_compact: Option<f64>) {
+    w.usize(specs.len());
+    for cs in specs.iter() {
+        w.u8(column_code(&cs.kind));
+        w.bool(cs.ordered);
+        w.bool(cs.nullable);
+        w.bool(cs.key);
+        w.bool(cs.unique);
+        match cs.name {
+            Some(ref name) => {
+                w.bool(true);
+                w.str(name);
+            }
+            None => w.bool(false),
+        }
+        w.bool(cs.dictionary);
+        w.bool(cs.case_insensitive);
+        w.usize(cs.stop_words.len());
+        for sw in cs.stop_words.iter() {
+            w.str(sw);
+        }
+    }
+    match auto_compact {
+        Some(ratio) => {
+            w.bool(true);
+            w.f64(ratio);
+        }
+        None => w.bool(false),
+    }
+}
+
+pub fn load_schema(name: &str, r: &mut Reader) -> Result<BucketBuilder, Error> {
+    let mut bb = BucketBuilder::new(name);
+    let n = try!(r.usize());
+    for _ in 0..n {
+        let mut cs = ColumnSpec::new(try!(column_kind(try!(r.u8()))));
+        cs.ordered = try!(r.bool());
+        cs.nullable = try!(r.bool());
+        cs.key = try!(r.bool());
+        cs.unique = try!(r.bool());
+        if try!(r.bool()) {
+            cs.name = Some(try!(r.string()));
+        }
+        cs.dictionary = try!(r.bool());
+        cs.case_insensitive = try!(r.bool());
+        let sws = try!(r.usize());
+        for _ in 0..sws {
+            cs.stop_words.push(try!(r.string()));
+        }
+        bb = bb.add_column(cs);
+    }
+    if try!(r.bool()) {
+        bb = bb.auto_compact(try!(r.f64()));
+    }
+    Ok(bb)
+}
+
+fn column_code(c: &ColumnBuilder) -> u8 {
+    match *c {
+        ColumnBuilder::UInt => 0,
+        ColumnBuilder::Int => 1,
+        ColumnBuilder::Boolean => 2,
+        ColumnBuilder::Str => 3,
+        ColumnBuilder::OwnedStr => 4,
+        ColumnBuilder::Float => 5,
+        ColumnBuilder::Text => 6,
+        ColumnBuilder::Tags => 7,
+    }
+}
+
+fn column_kind(code: u8) -> Result<ColumnBuilder, Error> {
+    match code {
+        0 => Ok(ColumnBuilder::UInt),
+        1 => Ok(ColumnBuilder::Int),
+        2 => Ok(ColumnBuilder::Boolean),
+        3 => Ok(ColumnBuilder::Str),
+        4 => Ok(ColumnBuilder::OwnedStr),
+        5 => Ok(ColumnBuilder::Float),
+        6 => Ok(ColumnBuilder::Text),
+        7 => Ok(ColumnBuilder::Tags),
+        _ => Err(Error::Corrupt("unknown column type")),
+    }
+}
+
+// the match finding rows with exactly this value, text is checked against the row afterwards
+fn exact_match<'a>(col: &Column, v: &Value<'a>) -> Match<'a> {
+    match (col, v) {
+        (&Column::Text, _) => Match::Any,
+        (_, &Value::Tags(ref ts)) => {
+            if ts.is_empty() {
+                Match::Any
+            } else {
+                Match::HasAll(ts.clone())
+            }
+        }
+        (_, &Value::UInt(u)) => Match::UInt(u),
+        (_, &Value::Int(i)) => Match::Int(i),
+        (_, &Value::Boolean(b)) => Match::Boolean(b),
+        (_, &Value::Str(s)) => Match::Str(s),
+        (_, &Value::OwnedStr(ref s)) => Match::OwnedStr(s.clone()),
+        // a shared string can not be lent out for 'a, In finds it by value
+        (_, &Value::Shared(_)) => Match::In(vec![v.clone()]),
+        (_, &Value::Float(x)) => Match::Float(x),
+        (_, &Value::Null) => Match::Null,
+    }
+}
+
+// equality as the indices see it
+fn same_value(l: &Value, r: &Value) -> bool {
+    match (l, r) {
+        (&Value::UInt(a), &Value::UInt(b)) => a == b,
+        (&Value::Int(a), &Value::Int(b)) => a == b,
+        (&Value::Boolean(a), &Value::Boolean(b)) => a == b,
+        (&Value::Float(a), &Value::Float(b)) => FloatKey::new(a) == FloatKey::new(b),
+        (&Value::Null, &Value::Null) => true,
+        // tags are deduplicated on the way in, so a subset of the same size is the same set
+        (&Value::Tags(ref a), &Value::Tags(ref b)) => {
+            let a: HashSet<Tag> = a.iter().map(Tag::new).collect();
+            a.len() == b.len() && b.iter().all(|t| a.contains(&Tag::new(t)))
+        }
+        _ => {
+            match (l.as_str(), r.as_str()) {
+                (Some(a), Some(b)) => a == b,
+                _ => false,
+            }
+        }
+    }
+}
+
 fn value_type_eq(l: &Column, r: &Value) -> bool {
     match (l, r) {
         (&Column::UInt, &Value::UInt(_)) => true,
@@ -321,18 +1232,59 @@ fn value_type_eq(l: &Column, r: &Value) -> bool {
         (&Column::Boolean, &Value::Boolean(_)) => true,
         (&Column::Str, &Value::Str(_)) => true,
         (&Column::OwnedStr, &Value::OwnedStr(_)) => true,
+        (&Column::Float, &Value::Float(_)) => true,
+        (&Column::Text, &Value::Str(_)) | (&Column::Text, &Value::OwnedStr(_)) => true,
+        (&Column::Str, &Value::Shared(_)) |
+        (&Column::OwnedStr, &Value::Shared(_)) |
+        (&Column::Text, &Value::Shared(_)) => true,
+        (&Column::Tags, &Value::Tags(ref ts)) => ts.iter().all(is_tag),
+        _ => false,
+    }
+}
+
+fn is_tag(v: &Value) -> bool {
+    match *v {
+        Value::UInt(_) | Value::Int(_) | Value::Str(_) | Value::OwnedStr(_) |
+        Value::Shared(_) => true,
         _ => false,
     }
 }
 
-fn validate_insert_value(cols: &Vec<Column>, vals: &[Value]) -> Result<(), Error> {
+fn dedup_tags(vals: &mut Vec<Value>) {
+    for v in vals.iter_mut() {
+        dedup_tag_value(v);
+    }
+}
+
+fn dedup_tag_value(v: &mut Value) {
+    if let Value::Tags(ref mut ts) = *v {
+        let mut seen = HashSet::new();
+        ts.retain(|t| seen.insert(Tag::new(t)));
+    }
+}
+
+fn validate_insert_value(cols: &Vec<Column>,
+                         nullable: &[bool],
+                         vals: &[Value])
+                         -> Result<(), Error> {
     if cols.len() != vals.len() {
         return Err(Error::WrongNumberOfValues(cols.len(), vals.len()));
     }
     for (i, col) in cols.iter().enumerate() {
-        if !value_type_eq(&col, &vals[i]) {
-            return Err(Error::WrongValueType(i));
+        try!(validate_value(col, nullable[i], i, &vals[i]));
+    }
+    Ok(())
+}
+
+fn validate_value(col: &Column, nullable: bool, i: usize, v: &Value) -> Result<(), Error> {
+    if let Value::Null = *v {
+        if !nullable {
+            return Err(Error::NotNullable(i));
         }
+        return Ok(());
+    }
+    if !value_type_eq(col, v) {
+        return Err(Error::WrongValueType(i));
     }
     Ok(())
 }
@@ -344,11 +1296,51 @@ fn match_simple_type_eq(l: &Column, r: &Match) -> bool {
         (&Column::Boolean, &Match::Boolean(_)) => true,
         (&Column::Str, &Match::Str(_)) => true,
         (&Column::OwnedStr, &Match::OwnedStr(_)) => true,
+        (&Column::Float, &Match::Float(_)) => true,
         (_, &Match::Any) => true,
+        (_, &Match::Null) | (_, &Match::NotNull) => true,
+        (&Column::Str, &Match::Prefix(_)) | (&Column::OwnedStr, &Match::Prefix(_)) => true,
+        (&Column::Str, &Match::IgnoreCase(_)) | (&Column::OwnedStr, &Match::IgnoreCase(_)) => true,
+        (&Column::Text, &Match::ContainsWord(_)) => true,
+        (&Column::Text, &Match::ContainsAllWords(_)) => true,
+        (&Column::Tags, &Match::HasAny(ref ts)) | (&Column::Tags, &Match::HasAll(ref ts)) => {
+            ts.iter().all(is_tag)
+        }
+        // text and tags have no single value to compare against
+        (&Column::Text, _) | (&Column::Tags, _) => false,
+        (_, &Match::Not(ref v)) => value_type_eq(l, v),
+        (_, &Match::In(ref vals)) => {
+            vals.iter().all(|v| {
+                match *v {
+                    Value::Null => true,
+                    _ => value_type_eq(l, v),
+                }
+            })
+        }
+        (_, m) => {
+            match m.bounds() {
+                Some((lo, hi)) => is_ordinal(l) && bound_type_eq(l, lo) && bound_type_eq(l, hi),
+                None => false,
+            }
+        }
+    }
+}
+
+// columns whose values have a meaningful order for range matches
+fn is_ordinal(c: &Column) -> bool {
+    match *c {
+        Column::UInt | Column::Int | Column::Float => true,
         _ => false,
     }
 }
 
+fn bound_type_eq(l: &Column, b: Bound<&Value>) -> bool {
+    match b {
+        Bound::Included(v) | Bound::Excluded(v) => value_type_eq(l, v),
+        Bound::Unbounded => true,
+    }
+}
+
 fn validate_find_simple_pattern(cols: &Vec<Column>, matches: &[Match]) -> Result<(), Error> {
     if cols.len() != matches.len() {
         return Err(Error::WrongNumberOfMatches(cols.len(), matches.len()));
@@ -376,6 +1368,8 @@ fn single_pattern_type_match<'a>(refcol: &Column, refv: &Value<'a>) -> Result<()
         (&Column::Boolean, &Value::Boolean(_)) => Ok(()),
         (&Column::Str, &Value::Str(_)) => Ok(()),
         (&Column::OwnedStr, &Value::OwnedStr(_)) => Ok(()),
+        (&Column::Str, &Value::Shared(_)) | (&Column::OwnedStr, &Value::Shared(_)) => Ok(()),
+        (&Column::Float, &Value::Float(_)) => Ok(()),
         _ => Err(Error::InvalidColumnMatch),
     }
 }
@@ -398,34 +1392,340 @@ impl<'a, 'b: 'a> Deref for ReadHandle<'a, 'b> {
     }
 }
 
+const OP_INSERT: u8 = 0;
+const OP_INSERT_UNIQUE: u8 = 1;
+const OP_UPSERT: u8 = 2;
+const OP_DELETE: u8 = 3;
+const OP_UPDATE: u8 = 4;
+const OP_COMPACT: u8 = 5;
+
+// where a WriteHandle logs the ops the bucket took: straight to the cache's log, or to the
+// buffer of a transaction that is written out as one record when it commits
+pub enum Journal<'a> {
+    Wal(&'a mut Wal, &'a str),
+    Buffer(&'a mut Writer),
+}
+
+impl<'a> Journal<'a> {
+    fn write(&mut self, ops: &Writer) -> Result<(), Error> {
+        match *self {
+            Journal::Wal(ref mut wal, name) => {
+                let mut rec = Writer::new();
+                rec.u8(RECORD_OPS);
+                rec.str(name);
+                rec.extend(ops);
+                wal.append(&rec)
+            }
+            Journal::Buffer(ref mut buf) => {
+                buf.extend(ops);
+                Ok(())
+            }
+        }
+    }
+}
+
 pub struct WriteHandle<'a, 'b: 'a> {
     b: &'a mut Bucket<'b>,
+    log: Option<Journal<'a>>,
 }
 
 impl<'a, 'b: 'a> WriteHandle<'a, 'b> {
     pub fn new(refb: &'a mut Bucket<'b>) -> Self {
-        WriteHandle { b: refb }
+        WriteHandle {
+            b: refb,
+            log: None,
+        }
+    }
+
+    pub fn with_journal(refb: &'a mut Bucket<'b>, log: Journal<'a>) -> Self {
+        WriteHandle {
+            b: refb,
+            log: Some(log),
+        }
+    }
+
+    // the op as the log records it, None without a log
+    fn op<F: FnOnce(&mut Writer)>(&self, f: F) -> Option<Writer> {
+        self.log.as_ref().map(|_| {
+            let mut op = Writer::new();
+            f(&mut op);
+            op
+        })
+    }
+
+    // an op is logged once the bucket took it, so one the bucket turns down is never logged.
+    // when the log can not take it the op is undone and the log's error returned
+    fn logged<T, F>(&mut self, op: Option<Writer>, f: F) -> Result<T, Error>
+        where F: FnOnce(&mut Bucket<'b>) -> Result<T, Error>
+    {
+        let op = match op {
+            Some(op) => op,
+            None => return f(self.b),
+        };
+        let outer = self.b.undo.is_none();
+        if outer {
+            self.b.undo = Some(Vec::new());
+        }
+        let mark = self.b.undo.as_ref().map_or(0, |u| u.len());
+        let stats = self.b.stats.clone();
+        let r = f(self.b);
+        let logged = match self.log {
+            Some(ref mut j) if r.is_ok() => j.write(&op),
+            _ => Ok(()),
+        };
+        if logged.is_err() {
+            self.b.rollback(mark);
+            self.b.stats = stats;
+        }
+        if outer {
+            self.b.undo = None;
+        }
+        logged.and(r)
     }
 
     pub fn insert(&mut self, vals: Vec<Value<'b>>) -> Result<(), Error> {
-        self.b.insert(vals)
+        let op = self.op(|w| {
+            w.u8(OP_INSERT);
+            w.values(&vals);
+        });
+        self.logged(op, |b| b.insert(vals))
+    }
+
+    // values keyed by column name, columns left out are null
+    pub fn insert_named(&mut self, named: &[(&str, Value<'b>)]) -> Result<(), Error> {
+        let slots = try!(self.b.slots_by_name(named));
+        self.insert(slots.into_iter().map(|v| v.unwrap_or(Value::Null)).collect())
     }
 
     pub fn insert_unique(&mut self, vals: Vec<Value<'b>>) -> Result<bool, Error> {
-        self.b.insert_unique(vals)
+        let op = self.op(|w| {
+            w.u8(OP_INSERT_UNIQUE);
+            w.values(&vals);
+        });
+        self.logged(op, |b| b.insert_unique(vals))
+    }
+
+    pub fn upsert(&mut self, vals: Vec<Value<'b>>) -> Result<bool, Error> {
+        let op = self.op(|w| {
+            w.u8(OP_UPSERT);
+            w.values(&vals);
+        });
+        self.logged(op, |b| b.upsert(vals))
     }
 
     pub fn delete<'c>(&mut self, matches: &[Match<'c>]) -> Result<usize, Error> {
-        self.b.delete(matches)
+        if let Ok(Some(ref ids)) = self.b.find_id(matches) {
+            self.delete_ids(ids)
+        } else {
+            Ok(0)
+        }
     }
 
     pub fn delete_pattern<'c>(&mut self, pattern: &Pattern<'c>) -> Result<usize, Error> {
         if let Ok(Some(ref ids)) = self.b.find_pattern_internal(pattern) {
-            Ok(self.b.delete_by_ids(ids))
+            self.delete_ids(ids)
         } else {
             Ok(0)
         }
     }
+
+    // deletes and updates are logged by row id, replay reproduces the ids exactly
+    fn delete_ids(&mut self, ids: &[usize]) -> Result<usize, Error> {
+        let op = self.op(|w| {
+            w.u8(OP_DELETE);
+            w.id_list(ids);
+        });
+        self.logged(op, |b| Ok(b.delete_by_ids(ids)))
+    }
+
+    pub fn update<'c>(&mut self,
+                      matches: &[Match<'c>],
+                      assignments: Vec<(usize, Value<'b>)>)
+                      -> Result<usize, Error> {
+        try!(self.b.validate_assignments(&assignments));
+        match try!(self.b.find_id(matches)) {
+            Some(ids) => self.update_ids(&ids, assignments),
+            None => Ok(0),
+        }
+    }
+
+    pub fn update_pattern<'c>(&mut self,
+                              pattern: &Pattern<'c>,
+                              assignments: Vec<(usize, Value<'b>)>)
+                              -> Result<usize, Error> {
+        try!(self.b.validate_assignments(&assignments));
+        match try!(self.b.find_pattern_internal(pattern)) {
+            Some(ids) => self.update_ids(&ids, assignments),
+            None => Ok(0),
+        }
+    }
+
+    fn update_ids(&mut self,
+                  ids: &[usize],
+                  assignments: Vec<(usize, Value<'b>)>)
+                  -> Result<usize, Error> {
+        let op = self.op(|w| {
+            w.u8(OP_UPDATE);
+            w.id_list(ids);
+            w.usize(assignments.len());
+            for &(col, ref v) in assignments.iter() {
+                w.usize(col);
+                w.value(v);
+            }
+        });
+        self.logged(op, |b| b.update_ids(ids, assignments))
+    }
+
+    // how many deleted rows were dropped. a compaction that can not be logged is not done. the
+    // bucket never turns one down, so it is logged first and needs no copy to be undone
+    pub fn compact(&mut self) -> Result<usize, Error> {
+        if let Some(op) = self.op(|w| w.u8(OP_COMPACT)) {
+            try!(self.log.as_mut().unwrap().write(&op));
+        }
+        Ok(self.b.compact())
+    }
+
+    // reads every record into the bucket or, when any record fails, none of them. errors carry
+    // the line the record starts on
+    pub fn import_csv<R: Read>(&mut self, mut r: R, opts: &CsvOptions) -> Result<usize, Error> {
+        try!(opts.check());
+        let columns = self.b.columns.clone();
+        let nullable = self.b.nullable.clone();
+        let mut text = String::new();
+        try!(r.read_to_string(&mut text));
+        let mut records = try!(csv::records(&text, opts.delimiter, opts.quote)).into_iter();
+        let mut width = columns.len();
+        // the field each column is read from, None leaves the column null
+        let mut sources: Vec<Option<usize>> = (0..columns.len()).map(Some).collect();
+        if opts.headers {
+            if let Some((line, header)) = records.next() {
+                let at = |e| Error::AtLine(line, Box::new(e));
+                width = header.len();
+                sources = vec![None; columns.len()];
+                if opts.columns.is_empty() {
+                    // every header names its column, the way export_csv writes them
+                    for (pos, f) in header.iter().enumerate() {
+                        let col = match self.b.column_by_header(&f.text) {
+                            Some(col) => col,
+                            None => return Err(at(Error::NoColumnNamed(f.text.clone()))),
+                        };
+                        if sources[col].is_some() {
+                            return Err(at(Error::RepeatedColumn(f.text.clone())));
+                        }
+                        sources[col] = Some(pos);
+                    }
+                }
+                for &(ref name, col) in opts.columns.iter() {
+                    if col >= columns.len() {
+                        return Err(Error::NoSuchColumn(col));
+                    }
+                    match header.iter().position(|f| f.text == *name) {
+                        Some(pos) => sources[col] = Some(pos),
+                        None => {
+                            return Err(at(Error::Parse(format!("no header named {}", name))))
+                        }
+                    }
+                }
+            }
+        }
+        try!(self.transaction(|tx| {
+            let mut n = 0;
+            for (line, fields) in records {
+                if fields.len() != width {
+                    let e = Error::WrongNumberOfValues(width, fields.len());
+                    return Err(Error::AtLine(line, Box::new(e)));
+                }
+                let mut row = Vec::with_capacity(columns.len());
+                for (i, src) in sources.iter().enumerate() {
+                    let v = match *src {
+                        Some(pos) => csv::parse_value(&columns[i], nullable[i], i, &fields[pos], opts),
+                        None => Ok(Value::Null),
+                    };
+                    match v {
+                        Ok(v) => row.push(v),
+                        Err(e) => return Err(Error::AtLine(line, Box::new(e))),
+                    }
+                }
+                if let Err(e) = tx.insert(row) {
+                    return Err(Error::AtLine(line, Box::new(e)));
+                }
+                n += 1;
+            }
+            Ok(n)
+        }))
+    }
+
+    // reads one json object per line into the bucket, or none of them when any line fails.
+    // members go to the column of the same name, columns without a member are left null
+    pub fn import_ndjson<R: Read>(&mut self, mut r: R) -> Result<usize, Error> {
+        let columns = self.b.columns.clone();
+        let nullable = self.b.nullable.clone();
+        let mut text = String::new();
+        try!(r.read_to_string(&mut text));
+        let mut rows = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let at = |e| Error::AtLine(n + 1, Box::new(e));
+            let fields = try!(json::parse_object(line).map_err(&at)).0;
+            let mut row: Vec<Option<Value<'b>>> = vec![None; columns.len()];
+            for (k, j) in fields.into_iter() {
+                let i = match self.b.column_by_name(&k) {
+                    Some(i) => i,
+                    None => return Err(at(Error::NoColumnNamed(k))),
+                };
+                if row[i].is_some() {
+                    return Err(at(Error::RepeatedColumn(k)));
+                }
+                row[i] = Some(try!(json::to_value(&columns[i], nullable[i], i, &j).map_err(&at)));
+            }
+            rows.push((n + 1, row));
+        }
+        try!(self.transaction(|tx| {
+            let mut n = 0;
+            for (line, row) in rows {
+                let vals = row.into_iter().map(|v| v.unwrap_or(Value::Null)).collect();
+                if let Err(e) = tx.insert(vals) {
+                    return Err(Error::AtLine(line, Box::new(e)));
+                }
+                n += 1;
+            }
+            Ok(n)
+        }))
+    }
+
+    // f writes to the bucket itself while every change is recorded, when f returns Err the
+    // changes are undone. with a log, the ops of f are written out together when it commits,
+    // should that fail they are undone as well and the log's error is returned around f's result
+    pub fn transaction<T, E, F>(&mut self, f: F) -> Result<Result<T, E>, Error>
+        where F: for<'t> FnOnce(&mut WriteHandle<'t, 'b>) -> Result<T, E>
+    {
+        let outer = self.b.undo.is_none();
+        if outer {
+            self.b.undo = Some(Vec::new());
+        }
+        let mark = self.b.undo.as_ref().map_or(0, |u| u.len());
+        let stats = self.b.stats.clone();
+        let mut ops = Writer::new();
+        let r = if self.log.is_some() {
+            f(&mut WriteHandle::with_journal(self.b, Journal::Buffer(&mut ops)))
+        } else {
+            f(&mut WriteHandle::new(self.b))
+        };
+        let logged = match self.log {
+            Some(ref mut j) if r.is_ok() && !ops.bytes().is_empty() => j.write(&ops),
+            _ => Ok(()),
+        };
+        if r.is_err() || logged.is_err() {
+            self.b.rollback(mark);
+            self.b.stats = stats;
+        }
+        if outer {
+            self.b.undo = None;
+        }
+        logged.map(|_| r)
+    }
 }
 
 impl<'a, 'b: 'a> Deref for WriteHandle<'a, 'b> {
@@ -438,7 +1738,9 @@ impl<'a, 'b: 'a> Deref for WriteHandle<'a, 'b> {
 
 pub struct BucketBuilder {
     pub name: String,
-    pub columns: Vec<ColumnBuilder>,
+    pub columns: Vec<ColumnSpec>,
+    pub auto_compact: Option<f64>,
+    pub snapshots: bool,
 }
 
 impl BucketBuilder {
@@ -446,11 +1748,25 @@ impl BucketBuilder {
         BucketBuilder {
             name: name.into(),
             columns: Vec::new(),
+            auto_compact: None,
+            snapshots: false,
         }
     }
 
-    pub fn add_column(mut self, col: ColumnBuilder) -> Self {
-        self.columns.push(col);
+    pub fn add_column<T: Into<ColumnSpec>>(mut self, col: T) -> Self {
+        self.columns.push(col.into());
+        self
+    }
+
+    // compact once this fraction of the stored rows is deleted
+    pub fn auto_compact(mut self, ratio: f64) -> Self {
+        self.auto_compact = Some(ratio);
+        self
+    }
+
+    // readers of a SharedCache only see the writes up to the last publish
+    pub fn snapshots(mut self) -> Self {
+        self.snapshots = true;
         self
     }
 }
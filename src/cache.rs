@@ -3,10 +3,19 @@ use fnv::FnvHasher;
 
 use std::collections::HashMap;
 use std::fmt;
+use std::fs::{self, File};
 use std::hash::BuildHasherDefault;
+use std::io::{Read, Write};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
 
 use errs::Error;
-use bucket::{BucketBuilder, Bucket, ReadHandle, WriteHandle, BucketStats};
+use bucket::{BucketBuilder, Bucket, ReadHandle, WriteHandle, BucketStats, Journal, load_schema,
+             save_schema};
+use persist::{Reader, Writer};
+use value::Interner;
+use wal::{self, FsyncPolicy, Wal, RECORD_DROP_BUCKET, RECORD_NEW_BUCKET, RECORD_OPS};
 
 #[derive(Debug)]
 pub struct CacheStats {
@@ -17,6 +26,26 @@ pub struct CacheStats {
     rows: usize,
 }
 
+impl CacheStats {
+    fn new() -> Self {
+        CacheStats {
+            buckets: HashMap::new(),
+            columns: 0,
+            inserts: 0,
+            deletes: 0,
+            rows: 0,
+        }
+    }
+
+    fn add(&mut self, name: &str, bs: BucketStats) {
+        self.columns += bs.columns;
+        self.inserts += bs.inserts;
+        self.deletes += bs.deletes;
+        self.rows += bs.inserts - bs.deletes;
+        self.buckets.insert(name.to_string(), bs);
+    }
+}
+
 impl fmt::Display for CacheStats {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         for (name, bstats) in self.buckets.iter() {
@@ -36,37 +65,100 @@ impl fmt::Display for CacheStats {
 
 pub struct Cache<'c> {
     buckets: HashMap<String, Bucket<'c>, BuildHasherDefault<FnvHasher>>,
+    // sequence number of the last logged change in the buckets
+    lsn: u64,
+    wal: Option<Wal>,
+    snapshot: Option<PathBuf>,
 }
 
 impl<'c> Cache<'c> {
     pub fn new() -> Self {
-        Cache { buckets: HashMap::default() }
+        Cache {
+            buckets: HashMap::default(),
+            lsn: 0,
+            wal: None,
+            snapshot: None,
+        }
     }
 
-    pub fn stats(&self) -> CacheStats {
-        let mut s = CacheStats {
-            buckets: HashMap::new(),
-            columns: 0,
-            inserts: 0,
-            deletes: 0,
-            rows: 0,
+    // a durable cache: the snapshot at `snapshot`, if there is one, with the log at `wal`
+    // replayed on top. every change from then on is logged before it is applied. both files
+    // are read into buf, borrowed string columns point into it
+    pub fn open<P: AsRef<Path>, Q: AsRef<Path>>(snapshot: P,
+                                                wal: Q,
+                                                policy: FsyncPolicy,
+                                                buf: &'c mut Vec<u8>)
+                                                -> Result<Self, Error> {
+        buf.clear();
+        if snapshot.as_ref().exists() {
+            try!(try!(File::open(snapshot.as_ref())).read_to_end(buf));
+        }
+        let split = buf.len();
+        if wal.as_ref().exists() {
+            try!(try!(File::open(wal.as_ref())).read_to_end(buf));
+        }
+        let data: &'c Vec<u8> = buf;
+        let mut c = if split > 0 {
+            try!(Cache::read(&data[..split]))
+        } else {
+            Cache::new()
         };
+        let (records, valid) = try!(wal::read(&data[split..]));
+        for mut r in records.into_iter() {
+            try!(c.replay(&mut r));
+        }
+        c.wal = Some(try!(Wal::open(wal, valid, c.lsn, policy)));
+        c.snapshot = Some(snapshot.as_ref().to_path_buf());
+        Ok(c)
+    }
+
+    // open without a caller's buffer, borrowed strings are copied once into shared ones so
+    // the cache can outlive the files and move between threads
+    pub fn open_owned<P: AsRef<Path>, Q: AsRef<Path>>(snapshot: P,
+                                                      wal: Q,
+                                                      policy: FsyncPolicy)
+                                                      -> Result<Cache<'static>, Error> {
+        let mut buf = Vec::new();
+        let c = try!(Cache::open(snapshot, wal, policy, &mut buf));
+        Ok(c.into_owned())
+    }
+
+    // the same cache without the borrow, see Bucket::into_owned
+    pub fn into_owned(self) -> Cache<'static> {
+        let mut strs = Interner::new();
+        Cache {
+            buckets: self.buckets
+                .into_iter()
+                .map(|(name, b)| (name, b.into_owned(&mut strs)))
+                .collect(),
+            lsn: self.lsn,
+            wal: self.wal,
+            snapshot: self.snapshot,
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let mut s = CacheStats::new();
         for (name, bucket) in self.buckets.iter() {
-            let bs = bucket.stats();
-            s.columns += bs.columns;
-            s.inserts += bs.inserts;
-            s.deletes += bs.deletes;
-            s.rows += bs.inserts - bs.deletes;
-            s.buckets.insert(name.to_string(), bs);
+            s.add(name, bucket.stats());
         }
         s
     }
 
     pub fn new_bucket(&mut self, bb: BucketBuilder) -> Result<(), Error> {
-        let name = bb.name;
-        let rb = Bucket::new(bb.columns);
+        let name = bb.name.clone();
+        let mut rec = Writer::new();
+        if self.wal.is_some() {
+            rec.u8(RECORD_NEW_BUCKET);
+            rec.str(&name);
+            save_schema(&mut rec, &bb.columns, bb.auto_compact);
+        }
+        let rb = Bucket::new(bb);
         match rb {
             Ok(b) => {
+                if let Some(ref mut wal) = self.wal {
+                    try!(wal.append(&rec));
+                }
                 self.buckets.insert(name, b);
                 Ok(())
             }
@@ -87,19 +179,298 @@ impl<'c> Cache<'c> {
         }
     }
 
+    // an immutable view of the bucket as it is now. it shares the bucket's indices and value
+    // chunks, a later write copies the ones it touches
+    pub fn snapshot(&self, bucket_name: &str) -> Option<Snapshot<'c>> {
+        self.buckets.get(bucket_name).map(|b| Snapshot { b: Arc::new(b.clone()) })
+    }
+
     pub fn bucket_mut<F>(&mut self, bucket_name: &str, closure: F)
         where F: FnOnce(Option<WriteHandle>)
     {
-        match self.buckets.get_mut(bucket_name) {
-            Some(b) => {
-                b.write().unwrap();
-                closure(Some(WriteHandle::new(b)))
+        match (self.buckets.get_mut(bucket_name), &mut self.wal) {
+            (Some(b), &mut Some(ref mut wal)) => {
+                closure(Some(WriteHandle::with_journal(b, Journal::Wal(wal, bucket_name))))
             }
+            (Some(b), _) => closure(Some(WriteHandle::new(b))),
             _ => closure(None),
         }
     }
 
+    // with a log, a drop that can not be logged leaves the bucket where it is. try_drop_bucket
+    // says why
     pub fn drop_bucket(&mut self, bucket_name: &str) {
+        let _ = self.try_drop_bucket(bucket_name);
+    }
+
+    // drops the bucket once the drop is logged, or fails with the log's error
+    pub fn try_drop_bucket(&mut self, bucket_name: &str) -> Result<(), Error> {
+        if !self.buckets.contains_key(bucket_name) {
+            return Ok(());
+        }
+        if let Some(ref mut wal) = self.wal {
+            let mut rec = Writer::new();
+            rec.u8(RECORD_DROP_BUCKET);
+            rec.str(bucket_name);
+            try!(wal.append(&rec));
+        }
         self.buckets.remove(bucket_name);
+        Ok(())
+    }
+
+    // writes every bucket to path, with the index bitmaps too when `indices` is set so a load
+    // does not have to rebuild them. the file is replaced only once it is completely written
+    pub fn save_to<P: AsRef<Path>>(&self, path: P, indices: bool) -> Result<(), Error> {
+        let mut w = Writer::new();
+        w.u64(match self.wal {
+            Some(ref wal) => wal.lsn(),
+            None => self.lsn,
+        });
+        w.usize(self.buckets.len());
+        for (name, b) in self.buckets.iter() {
+            w.str(name);
+            b.save(&mut w, indices);
+        }
+        let path = path.as_ref();
+        // next to the file under its full name, so no sibling that only differs in extension
+        // is overwritten
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        {
+            let mut f = try!(File::create(&tmp));
+            try!(f.write_all(&w.finish()));
+            try!(f.sync_all());
+        }
+        try!(fs::rename(&tmp, path));
+        Ok(())
+    }
+
+    // the file is read into buf, borrowed string columns point into it
+    pub fn load_from<P: AsRef<Path>>(path: P, buf: &'c mut Vec<u8>) -> Result<Self, Error> {
+        buf.clear();
+        try!(try!(File::open(path)).read_to_end(buf));
+        let data: &'c Vec<u8> = buf;
+        Cache::read(data)
+    }
+
+    // like load_from, with no buffer to keep around
+    pub fn load_owned<P: AsRef<Path>>(path: P) -> Result<Cache<'static>, Error> {
+        let mut buf = Vec::new();
+        let c = try!(Cache::load_from(path, &mut buf));
+        Ok(c.into_owned())
+    }
+
+    // saves to the snapshot the cache was opened from and empties the log. a crash in between
+    // is harmless, replay skips the records the snapshot already has
+    pub fn checkpoint(&mut self, indices: bool) -> Result<(), Error> {
+        let path = match self.snapshot {
+            Some(ref p) => p.clone(),
+            None => return Err(Error::NoLog),
+        };
+        try!(self.save_to(path, indices));
+        match self.wal {
+            Some(ref mut wal) => wal.truncate(),
+            None => Ok(()),
+        }
+    }
+
+    fn read(data: &'c [u8]) -> Result<Self, Error> {
+        let mut r = try!(Reader::open(data));
+        let mut c = Cache::new();
+        c.lsn = try!(r.u64());
+        let n = try!(r.usize());
+        for _ in 0..n {
+            let name = try!(r.string());
+            let b = try!(Bucket::load(&name, &mut r));
+            c.buckets.insert(name, b);
+        }
+        if !r.done() {
+            return Err(Error::Corrupt("trailing data"));
+        }
+        Ok(c)
+    }
+
+    fn replay(&mut self, r: &mut Reader<'c>) -> Result<(), Error> {
+        let lsn = try!(r.u64());
+        if lsn <= self.lsn {
+            return Ok(());
+        }
+        self.lsn = lsn;
+        let kind = try!(r.u8());
+        let name = try!(r.str());
+        match kind {
+            RECORD_NEW_BUCKET => {
+                let b = try!(Bucket::new(try!(load_schema(name, r))));
+                self.buckets.insert(name.to_owned(), b);
+            }
+            RECORD_DROP_BUCKET => {
+                self.buckets.remove(name);
+            }
+            RECORD_OPS => {
+                match self.buckets.get_mut(name) {
+                    Some(b) => {
+                        while !r.done() {
+                            try!(b.replay(r));
+                        }
+                    }
+                    None => return Err(Error::Corrupt("log refers to a missing bucket")),
+                }
+            }
+            _ => return Err(Error::Corrupt("unknown log record")),
+        }
+        Ok(())
+    }
+}
+
+// a cache that can be shared between threads, every bucket has its own lock so readers of
+// one bucket run in parallel and only wait for writers of that same bucket
+pub struct SharedCache<'c> {
+    buckets: RwLock<HashMap<String, Arc<Slot<'c>>, BuildHasherDefault<FnvHasher>>>,
+}
+
+enum Slot<'c> {
+    // written in place, readers wait for the writer
+    Plain(RwLock<Bucket<'c>>),
+    Snapshots {
+        // the version writers work on, copied on write while a published version or a
+        // snapshot still shares it
+        head: RwLock<Arc<Bucket<'c>>>,
+        // what readers see, swapped in by publish
+        published: RwLock<Arc<Bucket<'c>>>,
+    },
+}
+
+impl<'c> Slot<'c> {
+    fn stats(&self) -> BucketStats {
+        match *self {
+            Slot::Plain(ref b) => b.read().unwrap().stats(),
+            Slot::Snapshots { ref head, .. } => head.read().unwrap().stats(),
+        }
+    }
+}
+
+impl<'c> SharedCache<'c> {
+    pub fn new() -> Self {
+        SharedCache { buckets: RwLock::new(HashMap::default()) }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let mut s = CacheStats::new();
+        for (name, slot) in self.buckets.read().unwrap().iter() {
+            s.add(name, slot.stats());
+        }
+        s
+    }
+
+    pub fn new_bucket(&self, bb: BucketBuilder) -> Result<(), Error> {
+        let name = bb.name.clone();
+        let snapshots = bb.snapshots;
+        let b = try!(Bucket::new(bb));
+        let slot = if snapshots {
+            let b = Arc::new(b);
+            Slot::Snapshots {
+                head: RwLock::new(b.clone()),
+                published: RwLock::new(b),
+            }
+        } else {
+            Slot::Plain(RwLock::new(b))
+        };
+        self.buckets.write().unwrap().insert(name, Arc::new(slot));
+        Ok(())
+    }
+
+    pub fn has_bucket(&self, bucket_name: &str) -> bool {
+        self.buckets.read().unwrap().contains_key(bucket_name)
+    }
+
+    // snapshot buckets are read from the last published version and never wait for writers
+    pub fn bucket<F>(&self, bucket_name: &str, closure: F)
+        where F: FnOnce(Option<ReadHandle>)
+    {
+        match self.get(bucket_name) {
+            Some(slot) => {
+                match *slot {
+                    Slot::Plain(ref b) => {
+                        let guard = b.read().unwrap();
+                        closure(Some(ReadHandle::new(&guard)))
+                    }
+                    Slot::Snapshots { ref published, .. } => {
+                        let b = published.read().unwrap().clone();
+                        closure(Some(ReadHandle::new(&b)))
+                    }
+                }
+            }
+            _ => closure(None),
+        }
+    }
+
+    pub fn bucket_mut<F>(&self, bucket_name: &str, closure: F)
+        where F: FnOnce(Option<WriteHandle>)
+    {
+        match self.get(bucket_name) {
+            Some(slot) => {
+                match *slot {
+                    Slot::Plain(ref b) => {
+                        let mut guard = b.write().unwrap();
+                        closure(Some(WriteHandle::new(&mut guard)))
+                    }
+                    Slot::Snapshots { ref head, .. } => {
+                        let mut guard = head.write().unwrap();
+                        closure(Some(WriteHandle::new(Arc::make_mut(&mut guard))))
+                    }
+                }
+            }
+            _ => closure(None),
+        }
+    }
+
+    // makes every write so far visible to readers of a snapshot bucket
+    pub fn publish(&self, bucket_name: &str) -> Result<(), Error> {
+        match self.get(bucket_name) {
+            Some(slot) => {
+                if let Slot::Snapshots { ref head, ref published } = *slot {
+                    let head = head.read().unwrap().clone();
+                    *published.write().unwrap() = head;
+                }
+                Ok(())
+            }
+            None => Err(Error::InvalidBucket),
+        }
+    }
+
+    // an immutable view that can be held for as long as needed. a snapshot bucket shares its
+    // published version, any other bucket is copied here. either way a copy shares the indices
+    // and value chunks until a write touches them
+    pub fn snapshot(&self, bucket_name: &str) -> Option<Snapshot<'c>> {
+        self.get(bucket_name).map(|slot| {
+            let b = match *slot {
+                Slot::Plain(ref b) => Arc::new(b.read().unwrap().clone()),
+                Slot::Snapshots { ref published, .. } => published.read().unwrap().clone(),
+            };
+            Snapshot { b: b }
+        })
+    }
+
+    // a bucket dropped while in use stays alive until its last handle is gone
+    pub fn drop_bucket(&self, bucket_name: &str) {
+        self.buckets.write().unwrap().remove(bucket_name);
+    }
+
+    // the map lock is only held long enough to clone the bucket out
+    fn get(&self, bucket_name: &str) -> Option<Arc<Slot<'c>>> {
+        self.buckets.read().unwrap().get(bucket_name).cloned()
+    }
+}
+
+pub struct Snapshot<'c> {
+    b: Arc<Bucket<'c>>,
+}
+
+impl<'c> Deref for Snapshot<'c> {
+    type Target = Bucket<'c>;
+
+    fn deref(&self) -> &Bucket<'c> {
+        &self.b
     }
 }
@@ -1,3 +1,5 @@
+use std::convert;
+
 use token::Token;
 
 #[derive(Debug, Clone)]
@@ -7,15 +9,155 @@ pub enum Column {
     Boolean,
     Str,
     OwnedStr,
+    Float,
+    Text,
+    Tags,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ColumnBuilder {
     UInt,
     Int,
     Boolean,
     Str,
     OwnedStr,
+    Float,
+    Text,
+    Tags,
+}
+
+impl ColumnBuilder {
+    pub fn ordered(self) -> ColumnSpec {
+        ColumnSpec::new(self).ordered()
+    }
+
+    pub fn nullable(self) -> ColumnSpec {
+        ColumnSpec::new(self).nullable()
+    }
+
+    pub fn stop_words(self, words: &[&str]) -> ColumnSpec {
+        ColumnSpec::new(self).stop_words(words)
+    }
+
+    pub fn key(self) -> ColumnSpec {
+        ColumnSpec::new(self).key()
+    }
+
+    pub fn unique(self) -> ColumnSpec {
+        ColumnSpec::new(self).unique()
+    }
+
+    pub fn named<T: Into<String>>(self, name: T) -> ColumnSpec {
+        ColumnSpec::new(self).named(name)
+    }
+
+    pub fn dictionary(self) -> ColumnSpec {
+        ColumnSpec::new(self).dictionary()
+    }
+
+    pub fn case_insensitive(self) -> ColumnSpec {
+        ColumnSpec::new(self).case_insensitive()
+    }
+
+    pub fn column(&self) -> Column {
+        match *self {
+            ColumnBuilder::UInt => Column::UInt,
+            ColumnBuilder::Int => Column::Int,
+            ColumnBuilder::Boolean => Column::Boolean,
+            ColumnBuilder::Str => Column::Str,
+            ColumnBuilder::OwnedStr => Column::OwnedStr,
+            ColumnBuilder::Float => Column::Float,
+            ColumnBuilder::Text => Column::Text,
+            ColumnBuilder::Tags => Column::Tags,
+        }
+    }
+}
+
+// a column type plus the per column options, what BucketBuilder::add_column collects
+#[derive(Debug, Clone)]
+pub struct ColumnSpec {
+    pub kind: ColumnBuilder,
+    pub ordered: bool,
+    pub nullable: bool,
+    pub stop_words: Vec<String>,
+    pub key: bool,
+    pub unique: bool,
+    pub name: Option<String>,
+    pub dictionary: bool,
+    pub case_insensitive: bool,
+}
+
+impl ColumnSpec {
+    pub fn new(kind: ColumnBuilder) -> Self {
+        ColumnSpec {
+            kind: kind,
+            ordered: false,
+            nullable: false,
+            stop_words: Vec::new(),
+            key: false,
+            unique: false,
+            name: None,
+            dictionary: false,
+            case_insensitive: false,
+        }
+    }
+
+    // keep the index sorted by value so range matches only visit the keys in range
+    pub fn ordered(mut self) -> Self {
+        self.ordered = true;
+        self
+    }
+
+    // accept Value::Null in this column
+    pub fn nullable(mut self) -> Self {
+        self.nullable = true;
+        self
+    }
+
+    // words a Text column leaves out of its index
+    pub fn stop_words(mut self, words: &[&str]) -> Self {
+        self.stop_words = words.iter().map(|w| w.to_string()).collect();
+        self
+    }
+
+    // part of the bucket's primary key, at most one live row per key
+    pub fn key(mut self) -> Self {
+        self.key = true;
+        self
+    }
+
+    // no two live rows share a value in this column, nulls excepted
+    pub fn unique(mut self) -> Self {
+        self.unique = true;
+        self
+    }
+
+    // what json and csv call the column. json leaves unnamed columns out, csv headers give
+    // them their index
+    pub fn named<T: Into<String>>(mut self, name: T) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    // keep each distinct string of an OwnedStr column once, rows share it instead of holding
+    // a copy each. pays off when few strings repeat over many rows
+    pub fn dictionary(mut self) -> Self {
+        self.dictionary = true;
+        self
+    }
+
+    // also key a string column's rows by the lowercased strings, so IgnoreCase is a lookup
+    // instead of a walk over every key
+    pub fn case_insensitive(mut self) -> Self {
+        self.case_insensitive = true;
+        self
+    }
+}
+
+impl convert::Into<ColumnSpec> for ColumnBuilder {
+    fn into(self) -> ColumnSpec {
+        ColumnSpec::new(self)
+    }
 }
 
 #[derive(Debug)]
@@ -0,0 +1,341 @@
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use column::Column;
+use errs::Error;
+use value::Value;
+
+#[derive(Debug, Clone)]
+pub struct CsvOptions {
+    pub delimiter: u8,
+    pub quote: u8,
+    pub headers: bool,
+    pub null: String,
+    pub tag_separator: char,
+    pub columns: Vec<(String, usize)>,
+}
+
+impl CsvOptions {
+    pub fn new() -> Self {
+        CsvOptions {
+            delimiter: b',',
+            quote: b'"',
+            headers: true,
+            null: String::new(),
+            tag_separator: '|',
+            columns: Vec::new(),
+        }
+    }
+
+    pub fn delimiter(mut self, d: u8) -> Self {
+        self.delimiter = d;
+        self
+    }
+
+    pub fn quote(mut self, q: u8) -> Self {
+        self.quote = q;
+        self
+    }
+
+    // whether the first record names the fields
+    pub fn headers(mut self, h: bool) -> Self {
+        self.headers = h;
+        self
+    }
+
+    // the unquoted field text standing for null in nullable columns, empty by default
+    pub fn null<T: Into<String>>(mut self, n: T) -> Self {
+        self.null = n.into();
+        self
+    }
+
+    // splits the elements of a Tags field. string elements that would read as numbers or hold
+    // the separator are quoted with the quote character
+    pub fn tag_separator(mut self, sep: char) -> Self {
+        self.tag_separator = sep;
+        self
+    }
+
+    // fills column `col` from the field under this header, once any column is mapped the
+    // unmapped ones are left null. without a mapping every header has to name a column, by
+    // its name or an unnamed column's index, and columns without a header are left null.
+    // without headers fields go to columns by position
+    pub fn map<T: Into<String>>(mut self, header: T, col: usize) -> Self {
+        self.columns.push((header.into(), col));
+        self
+    }
+
+    // records are split by bytes, a non ascii delimiter or quote would cut characters apart
+    pub fn check(&self) -> Result<(), Error> {
+        if !self.delimiter.is_ascii() {
+            return Err(Error::InvalidCsvOptions("the delimiter is not an ascii character"));
+        }
+        if !self.quote.is_ascii() {
+            return Err(Error::InvalidCsvOptions("the quote is not an ascii character"));
+        }
+        if !self.headers && !self.columns.is_empty() {
+            return Err(Error::InvalidCsvOptions("columns are mapped by header but headers are off"));
+        }
+        Ok(())
+    }
+}
+
+// one field of a record, quoted fields are never null
+pub struct Field {
+    pub text: String,
+    pub quoted: bool,
+}
+
+// splits csv text into records, each with the line it starts on. blank lines are skipped
+pub fn records(text: &str, delimiter: u8, quote: u8) -> Result<Vec<(usize, Vec<Field>)>, Error> {
+    let b = text.as_bytes();
+    let mut out = Vec::new();
+    let mut i = 0;
+    let mut line = 1;
+    while i < b.len() {
+        if newline(b, i) > 0 {
+            i += newline(b, i);
+            line += 1;
+            continue;
+        }
+        let start_line = line;
+        let mut fields = Vec::new();
+        loop {
+            let mut f = Field {
+                text: String::new(),
+                quoted: false,
+            };
+            if i < b.len() && b[i] == quote {
+                f.quoted = true;
+                i += 1;
+                let mut start = i;
+                loop {
+                    if i == b.len() {
+                        return Err(at_line(start_line, "unterminated quoted field"));
+                    }
+                    if b[i] == quote {
+                        f.text.push_str(&text[start..i]);
+                        if i + 1 < b.len() && b[i + 1] == quote {
+                            f.text.push(quote as char);
+                            i += 2;
+                            start = i;
+                            continue;
+                        }
+                        i += 1;
+                        break;
+                    }
+                    if b[i] == b'\n' {
+                        line += 1;
+                    }
+                    i += 1;
+                }
+            } else {
+                let start = i;
+                while i < b.len() && b[i] != delimiter && newline(b, i) == 0 {
+                    i += 1;
+                }
+                f.text.push_str(&text[start..i]);
+            }
+            fields.push(f);
+            if i == b.len() {
+                break;
+            }
+            if b[i] == delimiter {
+                i += 1;
+                continue;
+            }
+            if newline(b, i) > 0 {
+                i += newline(b, i);
+                line += 1;
+                break;
+            }
+            return Err(at_line(line, "unexpected character after a quoted field"));
+        }
+        out.push((start_line, fields));
+    }
+    Ok(out)
+}
+
+// the value for column `i` of type `col`, strings come out owned, shared for a Str column
+pub fn parse_value<'a>(col: &Column,
+                       nullable: bool,
+                       i: usize,
+                       f: &Field,
+                       opts: &CsvOptions)
+                       -> Result<Value<'a>, Error> {
+    if nullable && !f.quoted && f.text == opts.null {
+        return Ok(Value::Null);
+    }
+    let s = &f.text[..];
+    match *col {
+        Column::UInt => s.trim().parse().map(Value::UInt).map_err(|_| Error::WrongValueType(i)),
+        Column::Int => s.trim().parse().map(Value::Int).map_err(|_| Error::WrongValueType(i)),
+        Column::Float => s.trim().parse().map(Value::Float).map_err(|_| Error::WrongValueType(i)),
+        Column::Boolean => {
+            match &s.trim().to_lowercase()[..] {
+                "true" => Ok(Value::Boolean(true)),
+                "false" => Ok(Value::Boolean(false)),
+                _ => Err(Error::WrongValueType(i)),
+            }
+        }
+        Column::OwnedStr | Column::Text => Ok(Value::OwnedStr(s.to_owned())),
+        Column::Tags => parse_tags(s, i, opts).map(Value::Tags),
+        Column::Str => Ok(Value::Shared(Arc::from(s))),
+    }
+}
+
+pub fn format_value(v: &Value, opts: &CsvOptions) -> Field {
+    match *v {
+        Value::Null => {
+            Field {
+                text: opts.null.clone(),
+                quoted: false,
+            }
+        }
+        Value::Tags(ref ts) => {
+            let parts: Vec<String> = ts.iter().map(|t| format_tag(t, opts)).collect();
+            let text = parts.join(&opts.tag_separator.to_string());
+            let quoted = text == opts.null;
+            Field {
+                text: text,
+                quoted: quoted,
+            }
+        }
+        _ => {
+            let text = format!("{}", v);
+            // a value that reads like the null marker is quoted to keep it a value
+            let quoted = text == opts.null;
+            Field {
+                text: text,
+                quoted: quoted,
+            }
+        }
+    }
+}
+
+// the elements of a Tags field split at the separator. a quoted element is a string, an
+// unquoted one is a number when it reads as one, as 7 does, and a string otherwise
+fn parse_tags<'a>(s: &str, i: usize, opts: &CsvOptions) -> Result<Vec<Value<'a>>, Error> {
+    let q = opts.quote as char;
+    let sep = opts.tag_separator;
+    let mut out = Vec::new();
+    if s.is_empty() {
+        return Ok(out);
+    }
+    let mut chars = s.chars().peekable();
+    loop {
+        if chars.peek() == Some(&q) {
+            chars.next();
+            let mut t = String::new();
+            loop {
+                match chars.next() {
+                    Some(c) if c == q => {
+                        if chars.peek() != Some(&q) {
+                            break;
+                        }
+                        chars.next();
+                        t.push(q);
+                    }
+                    Some(c) => t.push(c),
+                    None => return Err(Error::WrongValueType(i)),
+                }
+            }
+            out.push(Value::OwnedStr(t));
+            match chars.next() {
+                None => break,
+                Some(c) if c == sep => continue,
+                Some(_) => return Err(Error::WrongValueType(i)),
+            }
+        }
+        let mut t = String::new();
+        let mut more = false;
+        while let Some(c) = chars.next() {
+            if c == sep {
+                more = true;
+                break;
+            }
+            t.push(c);
+        }
+        out.push(tag_value(t));
+        if !more {
+            break;
+        }
+    }
+    Ok(out)
+}
+
+fn tag_value<'a>(t: String) -> Value<'a> {
+    if let Ok(u) = t.parse() {
+        Value::UInt(u)
+    } else if let Ok(i) = t.parse() {
+        Value::Int(i)
+    } else {
+        Value::OwnedStr(t)
+    }
+}
+
+// a string tag is quoted when it would not read back as the same string
+fn format_tag(t: &Value, opts: &CsvOptions) -> String {
+    let q = opts.quote as char;
+    let s = match t.as_str() {
+        Some(s) => s,
+        None => return format!("{}", t),
+    };
+    let plain = !s.is_empty() && !s.contains(opts.tag_separator) && !s.starts_with(q) &&
+                s.parse::<u64>().is_err() && s.parse::<i64>().is_err();
+    if plain {
+        return s.to_owned();
+    }
+    let mut out = String::new();
+    out.push(q);
+    for c in s.chars() {
+        if c == q {
+            out.push(q);
+        }
+        out.push(c);
+    }
+    out.push(q);
+    out
+}
+
+pub fn write_record<W: Write>(w: &mut W, fields: &[Field], opts: &CsvOptions) -> io::Result<()> {
+    let q = opts.quote as char;
+    let mut line = String::new();
+    for (i, f) in fields.iter().enumerate() {
+        if i > 0 {
+            line.push(opts.delimiter as char);
+        }
+        let special = f.text
+                       .bytes()
+                       .any(|c| c == opts.delimiter || c == opts.quote || c == b'\n' || c == b'\r');
+        if f.quoted || special {
+            line.push(q);
+            for c in f.text.chars() {
+                if c == q {
+                    line.push(q);
+                }
+                line.push(c);
+            }
+            line.push(q);
+        } else {
+            line.push_str(&f.text);
+        }
+    }
+    line.push('\n');
+    w.write_all(line.as_bytes())
+}
+
+// length of the line break at i, if there is one
+fn newline(b: &[u8], i: usize) -> usize {
+    if b[i] == b'\n' {
+        1
+    } else if b[i] == b'\r' && i + 1 < b.len() && b[i + 1] == b'\n' {
+        2
+    } else {
+        0
+    }
+}
+
+fn at_line(line: usize, msg: &str) -> Error {
+    Error::AtLine(line, Box::new(Error::Parse(msg.to_owned())))
+}
@@ -1,4 +1,6 @@
+use std::convert;
 use std::fmt;
+use std::io;
 
 #[derive(Debug)]
 pub enum Error {
@@ -8,9 +10,30 @@ pub enum Error {
     InvalidColumnMatch,
     WrongNumberOfValues(usize, usize),
     WrongValueType(usize),
+    NotNullable(usize),
+    NoSuchColumn(usize),
+    InvalidKeyColumn(usize),
+    NoKey,
+    DuplicateKey(String),
+    InvalidUniqueColumn(usize),
+    UniqueViolation { column: usize, value: String },
     WrongNumberOfMatches(usize, usize),
     WrongMatchType(usize),
     NothingToMatch,
+    Io(io::Error),
+    UnsupportedVersion(u32),
+    ChecksumMismatch,
+    Corrupt(&'static str),
+    NoLog,
+    Parse(String),
+    AtLine(usize, Box<Error>),
+    DuplicateColumnName(String),
+    RepeatedColumn(String),
+    NoColumnNamed(String),
+    WrongJsonType { column: usize, expected: &'static str, found: &'static str },
+    SchemaMismatch(usize),
+    InvalidDictionaryColumn(usize),
+    InvalidCsvOptions(&'static str),
 }
 
 impl fmt::Display for Error {
@@ -34,6 +57,27 @@ impl fmt::Display for Error {
             Error::WrongValueType(idx) => {
                 write!(f, "wrong value type at column index: {}", idx)
             },
+            Error::NotNullable(idx) => {
+                write!(f, "null value for non nullable column at index: {}", idx)
+            },
+            Error::NoSuchColumn(idx) => {
+                write!(f, "no column at index: {}", idx)
+            },
+            Error::InvalidKeyColumn(idx) => {
+                write!(f, "column at index: {} can not be part of the key", idx)
+            },
+            Error::NoKey => {
+                write!(f, "bucket has no key column defined.")
+            },
+            Error::DuplicateKey(ref key) => {
+                write!(f, "a row with key ({}) already exists.", key)
+            },
+            Error::InvalidUniqueColumn(idx) => {
+                write!(f, "column at index: {} can not be unique", idx)
+            },
+            Error::UniqueViolation { column, ref value } => {
+                write!(f, "value {} already exists in unique column at index: {}", value, column)
+            },
             Error::WrongNumberOfMatches(expected, actual) => {
                 write!(f, "wrong number of matches, expected: {}, actual: {}.", expected, actual)
             },
@@ -43,6 +87,54 @@ impl fmt::Display for Error {
             Error::NothingToMatch => {
                 write!(f, "nothing to match, perhaps try some match that is not Any ?")
             },
+            Error::Io(ref e) => {
+                write!(f, "io error: {}", e)
+            },
+            Error::UnsupportedVersion(v) => {
+                write!(f, "unsupported snapshot format version: {}", v)
+            },
+            Error::ChecksumMismatch => {
+                write!(f, "snapshot checksum does not match its content.")
+            },
+            Error::Corrupt(what) => {
+                write!(f, "corrupt snapshot: {}", what)
+            },
+            Error::NoLog => {
+                write!(f, "cache was not opened with a write-ahead log.")
+            },
+            Error::Parse(ref msg) => {
+                write!(f, "parse error: {}", msg)
+            },
+            Error::AtLine(line, ref e) => {
+                write!(f, "line {}: {}", line, e)
+            },
+            Error::DuplicateColumnName(ref name) => {
+                write!(f, "column name {} is given more than once", name)
+            },
+            Error::RepeatedColumn(ref name) => {
+                write!(f, "column {} is given more than one value", name)
+            },
+            Error::NoColumnNamed(ref name) => {
+                write!(f, "no column is named {}", name)
+            },
+            Error::WrongJsonType { column, expected, found } => {
+                write!(f, "column at index: {} expects {} but found {}", column, expected, found)
+            },
+            Error::SchemaMismatch(idx) => {
+                write!(f, "column at index: {} does not fit the row type", idx)
+            },
+            Error::InvalidDictionaryColumn(idx) => {
+                write!(f, "column at index: {} can not be dictionary encoded", idx)
+            },
+            Error::InvalidCsvOptions(why) => {
+                write!(f, "invalid csv options: {}", why)
+            },
         }
     }
+}
+
+impl convert::From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
 }
\ No newline at end of file
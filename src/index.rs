@@ -4,121 +4,1121 @@ use fnv::FnvHasher;
 extern crate roaring;
 use roaring::RoaringBitmap;
 
-use std::cmp::Eq;
-use std::collections::HashMap;
+use std::borrow::{Borrow, Cow};
+use std::cmp::{Eq, Ord, Ordering};
+use std::collections::{BTreeMap, Bound, HashMap};
+use std::f64;
 use std::fmt;
 use std::hash::BuildHasherDefault;
-use std::hash::Hash;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::sync::Arc;
 
-use column::Column;
-use value::Value;
+use column::{Column, ColumnSpec};
+use value::{Interner, Value, NULL_CODE};
 use matches::Match;
+use persist::{Persist, Reader, Writer};
+use text::Tokenizer;
+use errs::Error;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct IndexStats {
     pub cardinality: usize,
+    pub nulls: usize,
+    // for a dictionary column, the bytes of its distinct strings and the bytes its rows save
+    // by holding a 4 byte code instead of a string each, less what a case folded map holds.
+    // both are 0 for other columns
+    pub string_bytes: usize,
+    pub saved_bytes: usize,
+    // whether the rows are also keyed by their lowercased strings, see
+    // ColumnSpec::case_insensitive
+    pub case_folded: bool,
 }
 
 impl fmt::Display for IndexStats {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "cardinality: {}", self.cardinality)
+        try!(writeln!(f, "cardinality: {}", self.cardinality));
+        try!(writeln!(f, "nulls      : {}", self.nulls));
+        try!(writeln!(f, "strings    : {} bytes", self.string_bytes));
+        try!(writeln!(f, "saved      : {} bytes", self.saved_bytes));
+        writeln!(f, "case folded: {}", self.case_folded)
     }
 }
 
-#[derive(Debug)]
-pub enum Index<'a> {
-    UInt(HashMap<usize, RoaringBitmap<usize>, BuildHasherDefault<FnvHasher>>),
-    Int(HashMap<usize, RoaringBitmap<usize>, BuildHasherDefault<FnvHasher>>),
-    Boolean(HashMap<bool, RoaringBitmap<usize>, BuildHasherDefault<FnvHasher>>),
-    Str(HashMap<&'a str, RoaringBitmap<usize>, BuildHasherDefault<FnvHasher>>),
-    OwnedStr(HashMap<String, RoaringBitmap<usize>, BuildHasherDefault<FnvHasher>>),
+// total order over f64 so floats can key an index: every NaN collapses into one key that
+// sorts above infinity, and -0.0 is folded into 0.0
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FloatKey(u64);
+
+impl FloatKey {
+    pub fn new(f: f64) -> FloatKey {
+        let f = if f.is_nan() {
+            f64::NAN
+        } else if f == 0.0 {
+            0.0
+        } else {
+            f
+        };
+        let bits = f.to_bits();
+        if bits >> 63 == 1 {
+            FloatKey(!bits)
+        } else {
+            FloatKey(bits | 1 << 63)
+        }
+    }
+}
+
+impl<'c> Persist<'c> for FloatKey {
+    fn save(&self, w: &mut Writer) {
+        w.u64(self.0)
+    }
+
+    fn load(r: &mut Reader<'c>) -> Result<Self, Error> {
+        r.u64().map(FloatKey)
+    }
+}
+
+// a key of a Str column, borrowed like the rows or shared with the row it came from. it
+// compares, hashes and borrows as the str it holds
+#[derive(Debug, Clone)]
+pub enum StrKey<'a> {
+    Borrowed(&'a str),
+    Shared(Arc<str>),
+}
+
+impl<'a> Borrow<str> for StrKey<'a> {
+    fn borrow(&self) -> &str {
+        match *self {
+            StrKey::Borrowed(s) => s,
+            StrKey::Shared(ref s) => s,
+        }
+    }
+}
+
+impl<'a> PartialEq for StrKey<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        Borrow::<str>::borrow(self) == Borrow::<str>::borrow(other)
+    }
+}
+
+impl<'a> Eq for StrKey<'a> {}
+
+impl<'a> PartialOrd for StrKey<'a> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a> Ord for StrKey<'a> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        Borrow::<str>::borrow(self).cmp(Borrow::<str>::borrow(other))
+    }
+}
+
+impl<'a> Hash for StrKey<'a> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Borrow::<str>::borrow(self).hash(state)
+    }
+}
+
+impl<'c> Persist<'c> for StrKey<'c> {
+    fn save(&self, w: &mut Writer) {
+        w.str(self.borrow())
+    }
+
+    fn load(r: &mut Reader<'c>) -> Result<Self, Error> {
+        r.str().map(StrKey::Borrowed)
+    }
+}
+
+// one element of a Tags value, borrowed and owned strings are the same tag and so are integers
+// of the same value, a non negative Int is kept as a UInt
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Tag {
+    UInt(u64),
+    Int(i64),
+    Str(String),
+}
+
+impl Tag {
+    pub fn new(v: &Value) -> Tag {
+        match *v {
+            Value::UInt(u) => Tag::UInt(u),
+            Value::Int(i) if i >= 0 => Tag::UInt(i as u64),
+            Value::Int(i) => Tag::Int(i),
+            Value::Str(s) => Tag::Str(s.to_owned()),
+            Value::OwnedStr(ref s) => Tag::Str(s.clone()),
+            Value::Shared(ref s) => Tag::Str(s.to_string()),
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<'c> Persist<'c> for Tag {
+    fn save(&self, w: &mut Writer) {
+        match *self {
+            Tag::UInt(u) => {
+                w.u8(0);
+                w.u64(u);
+            }
+            Tag::Int(i) => {
+                w.u8(1);
+                w.u64(i as u64);
+            }
+            Tag::Str(ref s) => {
+                w.u8(2);
+                w.str(s);
+            }
+        }
+    }
+
+    fn load(r: &mut Reader<'c>) -> Result<Self, Error> {
+        match try!(r.u8()) {
+            0 => Ok(Tag::UInt(try!(r.u64()))),
+            1 => Ok(Tag::Int(try!(r.u64()) as i64)),
+            2 => Ok(Tag::Str(try!(r.string()))),
+            _ => Err(Error::Corrupt("unknown tag type")),
+        }
+    }
+}
+
+// one column of a primary key, hashable whatever the column type
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum KeyPart {
+    UInt(u64),
+    Int(i64),
+    Boolean(bool),
+    Str(String),
+    Float(FloatKey),
+}
+
+impl KeyPart {
+    pub fn new(v: &Value) -> KeyPart {
+        match *v {
+            Value::UInt(u) => KeyPart::UInt(u),
+            Value::Int(i) => KeyPart::Int(i),
+            Value::Boolean(b) => KeyPart::Boolean(b),
+            Value::Str(s) => KeyPart::Str(s.to_owned()),
+            Value::OwnedStr(ref s) => KeyPart::Str(s.clone()),
+            Value::Shared(ref s) => KeyPart::Str(s.to_string()),
+            Value::Float(x) => KeyPart::Float(FloatKey::new(x)),
+            _ => unreachable!(),
+        }
+    }
+}
+
+// value -> row ids, either hashed for fast point lookups or ordered for ranges
+#[derive(Debug, Clone)]
+pub enum KeyMap<K: Eq + Hash + Ord> {
+    Hashed(HashMap<K, RoaringBitmap<usize>, BuildHasherDefault<FnvHasher>>),
+    Ordered(BTreeMap<K, RoaringBitmap<usize>>),
+}
+
+impl<K: Eq + Hash + Ord> KeyMap<K> {
+    pub fn new(ordered: bool) -> Self {
+        if ordered {
+            KeyMap::Ordered(BTreeMap::new())
+        } else {
+            KeyMap::Hashed(HashMap::default())
+        }
+    }
+
+    pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<&RoaringBitmap<usize>>
+        where K: Borrow<Q>,
+              Q: Eq + Hash + Ord
+    {
+        match *self {
+            KeyMap::Hashed(ref m) => m.get(key),
+            KeyMap::Ordered(ref m) => m.get(key),
+        }
+    }
+
+    pub fn insert(&mut self, key: K, id: usize) {
+        match *self {
+            KeyMap::Hashed(ref mut m) => {
+                m.entry(key).or_insert_with(RoaringBitmap::new).insert(id);
+            }
+            KeyMap::Ordered(ref mut m) => {
+                m.entry(key).or_insert_with(RoaringBitmap::new).insert(id);
+            }
+        }
+    }
+
+    // takes the id out of the key's bitmap, and the key out of the map once nothing is left
+    pub fn remove<Q: ?Sized>(&mut self, key: &Q, id: usize)
+        where K: Borrow<Q>,
+              Q: Eq + Hash + Ord
+    {
+        match *self {
+            KeyMap::Hashed(ref mut m) => {
+                let empty = match m.get_mut(key) {
+                    Some(b) => {
+                        b.remove(id);
+                        b.len() == 0
+                    }
+                    None => false,
+                };
+                if empty {
+                    m.remove(key);
+                }
+            }
+            KeyMap::Ordered(ref mut m) => {
+                let empty = match m.get_mut(key) {
+                    Some(b) => {
+                        b.remove(id);
+                        b.len() == 0
+                    }
+                    None => false,
+                };
+                if empty {
+                    m.remove(key);
+                }
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match *self {
+            KeyMap::Hashed(ref m) => m.len(),
+            KeyMap::Ordered(ref m) => m.len(),
+        }
+    }
+
+    // renumbers every bitmap, keys left without rows are dropped
+    pub fn remap(&mut self, ids: &[Option<usize>]) {
+        match *self {
+            KeyMap::Hashed(ref mut m) => {
+                let old = mem::replace(m, HashMap::default());
+                for (k, b) in old.into_iter() {
+                    let b = remap_bitmap(&b, ids);
+                    if b.len() > 0 {
+                        m.insert(k, b);
+                    }
+                }
+            }
+            KeyMap::Ordered(ref mut m) => {
+                let old = mem::replace(m, BTreeMap::new());
+                for (k, b) in old.into_iter() {
+                    let b = remap_bitmap(&b, ids);
+                    if b.len() > 0 {
+                        m.insert(k, b);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn save<'c>(&self, w: &mut Writer)
+        where K: Persist<'c>
+    {
+        w.usize(self.len());
+        match *self {
+            KeyMap::Hashed(ref m) => {
+                for (k, b) in m.iter() {
+                    k.save(w);
+                    w.ids(b);
+                }
+            }
+            KeyMap::Ordered(ref m) => {
+                for (k, b) in m.iter() {
+                    k.save(w);
+                    w.ids(b);
+                }
+            }
+        }
+    }
+
+    pub fn load<'c>(&mut self, r: &mut Reader<'c>, rows: usize) -> Result<(), Error>
+        where K: Persist<'c>
+    {
+        let n = try!(r.usize());
+        for _ in 0..n {
+            let k = try!(K::load(r));
+            let b = try!(r.ids(rows));
+            match *self {
+                KeyMap::Hashed(ref mut m) => {
+                    m.insert(k, b);
+                }
+                KeyMap::Ordered(ref mut m) => {
+                    m.insert(k, b);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // union of the bitmaps of every key within the bounds
+    pub fn range(&self, lo: Bound<K>, hi: Bound<K>) -> RoaringBitmap<usize> {
+        let mut out = RoaringBitmap::new();
+        if is_empty_range(&lo, &hi) {
+            return out;
+        }
+        match *self {
+            KeyMap::Hashed(ref m) => {
+                for (k, b) in m.iter() {
+                    if in_range(k, &lo, &hi) {
+                        out.union_with(b);
+                    }
+                }
+            }
+            KeyMap::Ordered(ref m) => {
+                for (_, b) in m.range((lo, hi)) {
+                    out.union_with(b);
+                }
+            }
+        }
+        out
+    }
+
+    pub fn map_keys<L, F>(self, mut f: F) -> KeyMap<L>
+        where L: Eq + Hash + Ord,
+              F: FnMut(K) -> L
+    {
+        match self {
+            KeyMap::Hashed(m) => KeyMap::Hashed(m.into_iter().map(|(k, b)| (f(k), b)).collect()),
+            KeyMap::Ordered(m) => KeyMap::Ordered(m.into_iter().map(|(k, b)| (f(k), b)).collect()),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Ord + Borrow<str>> KeyMap<K> {
+    // an ordered map only walks the keys sharing the prefix, a hashed one has to look at all
+    pub fn prefix(&self, p: &str) -> RoaringBitmap<usize> {
+        let mut out = RoaringBitmap::new();
+        match *self {
+            KeyMap::Hashed(ref m) => {
+                for (k, b) in m.iter() {
+                    if k.borrow().starts_with(p) {
+                        out.union_with(b);
+                    }
+                }
+            }
+            KeyMap::Ordered(ref m) => {
+                for (k, b) in m.range::<str, _>((Bound::Included(p), Bound::Unbounded)) {
+                    if !k.borrow().starts_with(p) {
+                        break;
+                    }
+                    out.union_with(b);
+                }
+            }
+        }
+        out
+    }
+
+    // the same rows keyed by the lowercased strings, keys differing only in case share one
+    pub fn folded(&self) -> KeyMap<String> {
+        let mut out = KeyMap::new(false);
+        match *self {
+            KeyMap::Hashed(ref m) => {
+                for (k, b) in m.iter() {
+                    out.union(k.borrow().to_lowercase(), b);
+                }
+            }
+            KeyMap::Ordered(ref m) => {
+                for (k, b) in m.iter() {
+                    out.union(k.borrow().to_lowercase(), b);
+                }
+            }
+        }
+        out
+    }
+
+    // without a folded map IgnoreCase has to compare every key
+    pub fn ignore_case(&self, s: &str) -> RoaringBitmap<usize> {
+        let s = s.to_lowercase();
+        let mut out = RoaringBitmap::new();
+        match *self {
+            KeyMap::Hashed(ref m) => {
+                for (k, b) in m.iter() {
+                    if k.borrow().to_lowercase() == s {
+                        out.union_with(b);
+                    }
+                }
+            }
+            KeyMap::Ordered(ref m) => {
+                for (k, b) in m.iter() {
+                    if k.borrow().to_lowercase() == s {
+                        out.union_with(b);
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+impl KeyMap<String> {
+    // the bytes held for the keys, each string with its length and capacity
+    pub fn key_bytes(&self) -> usize {
+        let key = |k: &String| k.len() + mem::size_of::<String>();
+        match *self {
+            KeyMap::Hashed(ref m) => m.keys().map(key).sum(),
+            KeyMap::Ordered(ref m) => m.keys().map(key).sum(),
+        }
+    }
+
+    fn union(&mut self, key: String, b: &RoaringBitmap<usize>) {
+        match *self {
+            KeyMap::Hashed(ref mut m) => {
+                m.entry(key).or_insert_with(RoaringBitmap::new).union_with(b);
+            }
+            KeyMap::Ordered(ref mut m) => {
+                m.entry(key).or_insert_with(RoaringBitmap::new).union_with(b);
+            }
+        }
+    }
+}
+
+// the index of a dictionary column. every distinct string is kept once and known by a code,
+// the rows hold codes and the bitmaps are kept by code
+#[derive(Debug, Clone)]
+pub struct Dictionary {
+    // only turns a string into its code, prefixes walk the entries
+    codes: HashMap<Arc<str>, u32, BuildHasherDefault<FnvHasher>>,
+    // by code, None once a string has no rows left, its code is handed out again
+    entries: Vec<Option<(Arc<str>, RoaringBitmap<usize>)>>,
+    free: Vec<u32>,
+}
+
+impl Dictionary {
+    pub fn new() -> Self {
+        Dictionary {
+            codes: HashMap::default(),
+            entries: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.codes.len()
+    }
+
+    pub fn code_of(&self, s: &str) -> Option<u32> {
+        self.codes.get(s).cloned()
+    }
+
+    // the string of a code some row holds
+    pub fn string(&self, c: u32) -> Arc<str> {
+        self.entry(c).0.clone()
+    }
+
+    pub fn get(&self, s: &str) -> Option<&RoaringBitmap<usize>> {
+        self.codes.get(s).map(|&c| &self.entry(c).1)
+    }
+
+    pub fn insert(&mut self, s: &str, id: usize) {
+        let c = self.code(s);
+        self.entry_mut(c).1.insert(id);
+    }
+
+    // a string goes once no row, deleted ones included, holds its code
+    pub fn remove(&mut self, s: &str, id: usize) {
+        let c = match self.codes.get(s) {
+            Some(&c) => c,
+            None => return,
+        };
+        let empty = {
+            let b = &mut self.entry_mut(c).1;
+            b.remove(id);
+            b.len() == 0
+        };
+        if empty {
+            self.release(c);
+        }
+    }
+
+    pub fn remap(&mut self, ids: &[Option<usize>]) {
+        for c in 0..self.entries.len() {
+            let empty = match self.entries[c] {
+                Some((_, ref mut b)) => {
+                    *b = remap_bitmap(b, ids);
+                    b.len() == 0
+                }
+                None => false,
+            };
+            if empty {
+                self.release(c as u32);
+            }
+        }
+    }
+
+    pub fn prefix(&self, p: &str) -> RoaringBitmap<usize> {
+        let mut out = RoaringBitmap::new();
+        for &(ref s, ref b) in self.entries.iter().filter_map(|e| e.as_ref()) {
+            if s.starts_with(p) {
+                out.union_with(b);
+            }
+        }
+        out
+    }
+
+    // the rows keyed by the lowercased strings, see Index::folded
+    pub fn folded(&self) -> KeyMap<String> {
+        let mut out = KeyMap::new(false);
+        for &(ref s, ref b) in self.entries.iter().filter_map(|e| e.as_ref()) {
+            out.union(s.to_lowercase(), b);
+        }
+        out
+    }
+
+    // see KeyMap::ignore_case
+    pub fn ignore_case(&self, s: &str) -> RoaringBitmap<usize> {
+        let s = s.to_lowercase();
+        let mut out = RoaringBitmap::new();
+        for &(ref k, ref b) in self.entries.iter().filter_map(|e| e.as_ref()) {
+            if k.to_lowercase() == s {
+                out.union_with(b);
+            }
+        }
+        out
+    }
+
+    // written as strings, codes are only meaningful to this dictionary
+    pub fn save(&self, w: &mut Writer) {
+        w.usize(self.len());
+        for &(ref s, ref b) in self.entries.iter().filter_map(|e| e.as_ref()) {
+            w.str(s);
+            w.ids(b);
+        }
+    }
+
+    pub fn load(&mut self, r: &mut Reader, rows: usize) -> Result<(), Error> {
+        let n = try!(r.usize());
+        for _ in 0..n {
+            let c = self.code(try!(r.str()));
+            let b = try!(r.ids(rows));
+            self.entry_mut(c).1 = b;
+        }
+        Ok(())
+    }
+
+    // the bytes held for the strings, and the bytes the rows save by holding a code each
+    // instead of a string of their own, after paying for the dictionary
+    pub fn stats(&self) -> (usize, usize) {
+        let mut bytes = 0;
+        let mut cells = 0;
+        for &(ref s, ref b) in self.entries.iter().filter_map(|e| e.as_ref()) {
+            // an Arc keeps its two counts in front of the string
+            bytes += s.len() + 2 * mem::size_of::<usize>();
+            cells += b.len() * (mem::size_of::<Value>() + s.len() - mem::size_of::<u32>());
+        }
+        (bytes, cells.saturating_sub(bytes))
+    }
+
+    // the code of s, which is added with no rows if it is new
+    fn code(&mut self, s: &str) -> u32 {
+        if let Some(&c) = self.codes.get(s) {
+            return c;
+        }
+        let shared: Arc<str> = Arc::from(s);
+        let entry = Some((shared.clone(), RoaringBitmap::new()));
+        let c = match self.free.pop() {
+            Some(c) => {
+                self.entries[c as usize] = entry;
+                c
+            }
+            None => {
+                self.entries.push(entry);
+                (self.entries.len() - 1) as u32
+            }
+        };
+        self.codes.insert(shared, c);
+        c
+    }
+
+    fn release(&mut self, c: u32) {
+        if let Some((s, _)) = self.entries[c as usize].take() {
+            self.codes.remove(&s);
+            self.free.push(c);
+        }
+    }
+
+    fn entry(&self, c: u32) -> &(Arc<str>, RoaringBitmap<usize>) {
+        self.entries[c as usize].as_ref().unwrap()
+    }
+
+    fn entry_mut(&mut self, c: u32) -> &mut (Arc<str>, RoaringBitmap<usize>) {
+        self.entries[c as usize].as_mut().unwrap()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Keys<'a> {
+    UInt(KeyMap<u64>),
+    Int(KeyMap<i64>),
+    Boolean(KeyMap<bool>),
+    Str(KeyMap<StrKey<'a>>),
+    // keys share the string of a row holding a shared one
+    OwnedStr(KeyMap<Arc<str>>),
+    Float(KeyMap<FloatKey>),
+    Text(KeyMap<String>, Tokenizer),
+    Tags(KeyMap<Tag>),
+    Dict(Dictionary),
+}
+
+#[derive(Debug, Clone)]
+pub struct Index<'a> {
+    keys: Keys<'a>,
+    nulls: RoaringBitmap<usize>,
+    // case insensitive string columns, dictionary ones too, also key their rows by the
+    // lowercased strings, for IgnoreCase
+    folded: Option<KeyMap<String>>,
 }
 
 impl<'a> Index<'a> {
-    pub fn new_by_column(col: &Column) -> Index<'a> {
-        match *col {
-            Column::UInt => Index::UInt(HashMap::default()),
-            Column::Int => Index::Int(HashMap::default()),
-            Column::Boolean => Index::Boolean(HashMap::default()),
-            Column::Str => Index::Str(HashMap::default()),
-            Column::OwnedStr => Index::OwnedStr(HashMap::default()),
+    pub fn new_by_column(col: &Column, spec: &ColumnSpec) -> Index<'a> {
+        let ordered = spec.ordered;
+        let keys = match *col {
+            Column::UInt => Keys::UInt(KeyMap::new(ordered)),
+            Column::Int => Keys::Int(KeyMap::new(ordered)),
+            Column::Boolean => Keys::Boolean(KeyMap::new(ordered)),
+            Column::Str => Keys::Str(KeyMap::new(ordered)),
+            Column::OwnedStr if spec.dictionary => Keys::Dict(Dictionary::new()),
+            Column::OwnedStr => Keys::OwnedStr(KeyMap::new(ordered)),
+            Column::Float => Keys::Float(KeyMap::new(ordered)),
+            Column::Text => Keys::Text(KeyMap::new(ordered), Tokenizer::new(&spec.stop_words)),
+            Column::Tags => Keys::Tags(KeyMap::new(ordered)),
+        };
+        let folded = match keys {
+            Keys::Str(_) | Keys::OwnedStr(_) | Keys::Dict(_) if spec.case_insensitive => {
+                Some(KeyMap::new(false))
+            }
+            _ => None,
+        };
+        Index {
+            keys: keys,
+            nulls: RoaringBitmap::new(),
+            folded: folded,
         }
     }
 
     pub fn insert(&mut self, val: &Value<'a>, id: usize) {
-        match (self, val) {
-            (&mut Index::UInt(ref mut m), &Value::UInt(u)) => {
-                ensure_bitmap(m, u);
-                if let Some(idx) = m.get_mut(&u) {
-                    idx.insert(id);
-                }
+        if let Some(ref mut f) = self.folded {
+            if let Some(s) = val.as_str() {
+                f.insert(s.to_lowercase(), id);
+            }
+        }
+        match (&mut self.keys, val) {
+            (_, &Value::Null) => {
+                self.nulls.insert(id);
+            }
+            (&mut Keys::UInt(ref mut m), &Value::UInt(u)) => m.insert(u, id),
+            (&mut Keys::Int(ref mut m), &Value::Int(i)) => m.insert(i, id),
+            (&mut Keys::Boolean(ref mut m), &Value::Boolean(tf)) => m.insert(tf, id),
+            (&mut Keys::Str(ref mut m), &Value::Str(s)) => m.insert(StrKey::Borrowed(s), id),
+            (&mut Keys::Str(ref mut m), &Value::Shared(ref s)) => {
+                m.insert(StrKey::Shared(s.clone()), id)
             }
-            (&mut Index::Int(ref mut m), &Value::Int(i)) => {
-                let u = i as usize;
-                ensure_bitmap(m, u);
-                if let Some(idx) = m.get_mut(&u) {
-                    idx.insert(id);
+            (&mut Keys::OwnedStr(ref mut m), &Value::OwnedStr(ref s)) => {
+                m.insert(Arc::from(&s[..]), id)
+            }
+            (&mut Keys::OwnedStr(ref mut m), &Value::Shared(ref s)) => m.insert(s.clone(), id),
+            (&mut Keys::Float(ref mut m), &Value::Float(x)) => m.insert(FloatKey::new(x), id),
+            (&mut Keys::Text(ref mut m, ref t), v) => {
+                for w in t.tokens(v.as_str().unwrap()) {
+                    m.insert(w, id);
                 }
             }
-            (&mut Index::Boolean(ref mut m), &Value::Boolean(tf)) => {
-                ensure_bitmap(m, tf);
-                if let Some(idx) = m.get_mut(&tf) {
-                    idx.insert(id);
+            (&mut Keys::Dict(ref mut d), v) => d.insert(v.as_str().unwrap(), id),
+            (&mut Keys::Tags(ref mut m), &Value::Tags(ref ts)) => {
+                for t in ts.iter() {
+                    m.insert(Tag::new(t), id);
                 }
             }
-            (&mut Index::Str(ref mut m), &Value::Str(s)) => {
-                ensure_bitmap(m, s);
-                if let Some(idx) = m.get_mut(s) {
-                    idx.insert(id);
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn remove<'b>(&mut self, val: &Value<'b>, id: usize) {
+        if let Some(ref mut f) = self.folded {
+            if let Some(s) = val.as_str() {
+                f.remove(&s.to_lowercase()[..], id);
+            }
+        }
+        match (&mut self.keys, val) {
+            (_, &Value::Null) => {
+                self.nulls.remove(id);
+            }
+            (&mut Keys::UInt(ref mut m), &Value::UInt(u)) => m.remove(&u, id),
+            (&mut Keys::Int(ref mut m), &Value::Int(i)) => m.remove(&i, id),
+            (&mut Keys::Boolean(ref mut m), &Value::Boolean(tf)) => m.remove(&tf, id),
+            (&mut Keys::Str(ref mut m), &Value::Str(s)) => m.remove(s, id),
+            (&mut Keys::Str(ref mut m), &Value::Shared(ref s)) => m.remove(&s[..], id),
+            (&mut Keys::OwnedStr(ref mut m), &Value::OwnedStr(ref s)) => m.remove(&s[..], id),
+            (&mut Keys::OwnedStr(ref mut m), &Value::Shared(ref s)) => m.remove(&s[..], id),
+            (&mut Keys::Float(ref mut m), &Value::Float(x)) => m.remove(&FloatKey::new(x), id),
+            (&mut Keys::Text(ref mut m, ref t), v) => {
+                for w in t.tokens(v.as_str().unwrap()) {
+                    m.remove(&w[..], id);
                 }
             }
-            (&mut Index::OwnedStr(ref mut m), &Value::OwnedStr(ref s)) => {
-                ensure_bitmap(m, s.clone());
-                if let Some(idx) = m.get_mut(s) {
-                    idx.insert(id);
+            (&mut Keys::Dict(ref mut d), v) => d.remove(v.as_str().unwrap(), id),
+            (&mut Keys::Tags(ref mut m), &Value::Tags(ref ts)) => {
+                for t in ts.iter() {
+                    m.remove(&Tag::new(t), id);
                 }
             }
             _ => unreachable!(),
         }
     }
 
-    pub fn get_match_index(&self, pattern: &Match) -> Option<&RoaringBitmap<usize>> {
-        match (self, pattern) {
-            (&Index::UInt(ref m), &Match::UInt(u)) => m.get(&u),
-            (&Index::Int(ref m), &Match::Int(i)) => m.get(&(i as usize)),
-            (&Index::Boolean(ref m), &Match::Boolean(tf)) => m.get(&tf),
-            (&Index::Str(ref m), &Match::Str(s)) => m.get(s),
-            (&Index::OwnedStr(ref m), &Match::OwnedStr(ref s)) => m.get(s),
+    // the code a row holds for val once it is inserted, None unless this is a dictionary
+    // column. a string the dictionary does not know gets the null code
+    pub fn code(&self, val: &Value) -> Option<u32> {
+        match (&self.keys, val) {
+            (&Keys::Dict(_), &Value::Null) => Some(NULL_CODE),
+            (&Keys::Dict(ref d), v) => {
+                Some(v.as_str().and_then(|s| d.code_of(s)).unwrap_or(NULL_CODE))
+            }
+            _ => None,
+        }
+    }
+
+    // the value a row holding this code has
+    pub fn decode(&self, c: u32) -> Value<'a> {
+        match self.keys {
+            Keys::Dict(_) if c == NULL_CODE => Value::Null,
+            Keys::Dict(ref d) => Value::Shared(d.string(c)),
             _ => unreachable!(),
         }
     }
 
+    pub fn nulls(&self) -> &RoaringBitmap<usize> {
+        &self.nulls
+    }
+
+    pub fn remap(&mut self, ids: &[Option<usize>]) {
+        match self.keys {
+            Keys::UInt(ref mut m) => m.remap(ids),
+            Keys::Int(ref mut m) => m.remap(ids),
+            Keys::Boolean(ref mut m) => m.remap(ids),
+            Keys::Str(ref mut m) => m.remap(ids),
+            Keys::OwnedStr(ref mut m) => m.remap(ids),
+            Keys::Float(ref mut m) => m.remap(ids),
+            Keys::Text(ref mut m, _) => m.remap(ids),
+            Keys::Tags(ref mut m) => m.remap(ids),
+            Keys::Dict(ref mut d) => d.remap(ids),
+        }
+        if let Some(ref mut f) = self.folded {
+            f.remap(ids);
+        }
+        self.nulls = remap_bitmap(&self.nulls, ids);
+    }
+
+    pub fn save(&self, w: &mut Writer) {
+        w.ids(&self.nulls);
+        match self.keys {
+            Keys::UInt(ref m) => m.save(w),
+            Keys::Int(ref m) => m.save(w),
+            Keys::Boolean(ref m) => m.save(w),
+            Keys::Str(ref m) => m.save(w),
+            Keys::OwnedStr(ref m) => m.save(w),
+            Keys::Float(ref m) => m.save(w),
+            Keys::Text(ref m, _) => m.save(w),
+            Keys::Tags(ref m) => m.save(w),
+            Keys::Dict(ref d) => d.save(w),
+        }
+    }
+
+    // fills an empty index with what save wrote, the folded keys are not saved but rebuilt
+    pub fn load(&mut self, r: &mut Reader<'a>, rows: usize) -> Result<(), Error> {
+        self.nulls = try!(r.ids(rows));
+        try!(match self.keys {
+            Keys::UInt(ref mut m) => m.load(r, rows),
+            Keys::Int(ref mut m) => m.load(r, rows),
+            Keys::Boolean(ref mut m) => m.load(r, rows),
+            Keys::Str(ref mut m) => m.load(r, rows),
+            Keys::OwnedStr(ref mut m) => m.load(r, rows),
+            Keys::Float(ref mut m) => m.load(r, rows),
+            Keys::Text(ref mut m, _) => m.load(r, rows),
+            Keys::Tags(ref mut m) => m.load(r, rows),
+            Keys::Dict(ref mut d) => d.load(r, rows),
+        });
+        if self.folded.is_some() {
+            self.folded = match self.keys {
+                Keys::Str(ref m) => Some(m.folded()),
+                Keys::OwnedStr(ref m) => Some(m.folded()),
+                Keys::Dict(ref d) => Some(d.folded()),
+                _ => None,
+            };
+        }
+        Ok(())
+    }
+
+    pub fn get_match_index(&self, pattern: &Match) -> Option<Cow<RoaringBitmap<usize>>> {
+        if let Some((lo, hi)) = pattern.bounds() {
+            let b = self.get_range_index(lo, hi);
+            return if b.len() == 0 {
+                None
+            } else {
+                Some(Cow::Owned(b))
+            };
+        }
+        let owned = match (&self.keys, pattern) {
+            (_, &Match::In(ref vals)) => Some(self.get_values_index(vals)),
+            (&Keys::Str(ref m), &Match::Prefix(p)) => Some(m.prefix(p)),
+            (&Keys::OwnedStr(ref m), &Match::Prefix(p)) => Some(m.prefix(p)),
+            (&Keys::Dict(ref d), &Match::Prefix(p)) => Some(d.prefix(p)),
+            (&Keys::Str(ref m), &Match::IgnoreCase(s)) if self.folded.is_none() => {
+                Some(m.ignore_case(s))
+            }
+            (&Keys::OwnedStr(ref m), &Match::IgnoreCase(s)) if self.folded.is_none() => {
+                Some(m.ignore_case(s))
+            }
+            (&Keys::Dict(ref d), &Match::IgnoreCase(s)) if self.folded.is_none() => {
+                Some(d.ignore_case(s))
+            }
+            // the word goes through the tokenizer like the text did, so "Rust," finds rust
+            (&Keys::Text(ref m, ref t), &Match::ContainsWord(w)) => Some(all_words(m, t, w)),
+            (&Keys::Text(ref m, ref t), &Match::ContainsAllWords(s)) => Some(all_words(m, t, s)),
+            (&Keys::Tags(ref m), &Match::HasAny(ref ts)) => Some(any_tags(m, ts)),
+            (&Keys::Tags(ref m), &Match::HasAll(ref ts)) => Some(all_tags(m, ts)),
+            _ => None,
+        };
+        if let Some(b) = owned {
+            return if b.len() == 0 {
+                None
+            } else {
+                Some(Cow::Owned(b))
+            };
+        }
+        let b = match (&self.keys, pattern) {
+            (_, &Match::Null) => {
+                if self.nulls.len() == 0 {
+                    None
+                } else {
+                    Some(&self.nulls)
+                }
+            }
+            (&Keys::UInt(ref m), &Match::UInt(u)) => m.get(&u),
+            (&Keys::Int(ref m), &Match::Int(i)) => m.get(&i),
+            (&Keys::Boolean(ref m), &Match::Boolean(tf)) => m.get(&tf),
+            (_, &Match::IgnoreCase(s)) => {
+                self.folded.as_ref().and_then(|f| f.get(&s.to_lowercase()[..]))
+            }
+            (&Keys::Str(ref m), &Match::Str(s)) => m.get(s),
+            (&Keys::OwnedStr(ref m), &Match::OwnedStr(ref s)) => m.get(&s[..]),
+            (&Keys::Dict(ref d), &Match::OwnedStr(ref s)) => d.get(s),
+            (&Keys::Float(ref m), &Match::Float(x)) => m.get(&FloatKey::new(x)),
+            _ => unreachable!(),
+        };
+        b.map(Cow::Borrowed)
+    }
+
     pub fn get_value_index<'b>(&self, pattern: &Value<'b>) -> Option<&RoaringBitmap<usize>> {
-        match (self, pattern) {
-            (&Index::UInt(ref m), &Value::UInt(u)) => m.get(&u),
-            (&Index::Int(ref m), &Value::Int(i)) => m.get(&(i as usize)),
-            (&Index::Boolean(ref m), &Value::Boolean(tf)) => m.get(&tf),
-            (&Index::Str(ref m), &Value::Str(s)) => m.get(s),
-            (&Index::OwnedStr(ref m), &Value::OwnedStr(ref s)) => m.get(s),
+        match (&self.keys, pattern) {
+            (&Keys::UInt(ref m), &Value::UInt(u)) => m.get(&u),
+            (&Keys::Int(ref m), &Value::Int(i)) => m.get(&i),
+            (&Keys::Boolean(ref m), &Value::Boolean(tf)) => m.get(&tf),
+            (&Keys::Str(ref m), &Value::Str(s)) => m.get(s),
+            (&Keys::Str(ref m), &Value::Shared(ref s)) => m.get(&s[..]),
+            (&Keys::OwnedStr(ref m), &Value::OwnedStr(ref s)) => m.get(&s[..]),
+            (&Keys::OwnedStr(ref m), &Value::Shared(ref s)) => m.get(&s[..]),
+            (&Keys::Float(ref m), &Value::Float(x)) => m.get(&FloatKey::new(x)),
+            (&Keys::Dict(ref d), v) => v.as_str().and_then(|s| d.get(s)),
             _ => unreachable!(),
         }
     }
 
+    // union of the bitmaps of every listed value
+    pub fn get_values_index<'b>(&self, vals: &[Value<'b>]) -> RoaringBitmap<usize> {
+        let mut out = RoaringBitmap::new();
+        for v in vals.iter() {
+            if let Value::Null = *v {
+                out.union_with(&self.nulls);
+            } else if let Some(b) = self.get_value_index(v) {
+                out.union_with(b);
+            }
+        }
+        out
+    }
+
+    pub fn get_range_index<'b>(&self,
+                               lo: Bound<&Value<'b>>,
+                               hi: Bound<&Value<'b>>)
+                               -> RoaringBitmap<usize> {
+        match self.keys {
+            Keys::UInt(ref m) => m.range(map_bound(lo, uint_key), map_bound(hi, uint_key)),
+            Keys::Int(ref m) => m.range(map_bound(lo, int_key), map_bound(hi, int_key)),
+            Keys::Float(ref m) => {
+                // NaN is not ordered against anything, it is only found by equality
+                if is_nan_bound(&lo) || is_nan_bound(&hi) {
+                    return RoaringBitmap::new();
+                }
+                let hi = match map_bound(hi, float_key) {
+                    Bound::Unbounded => Bound::Included(FloatKey::new(f64::INFINITY)),
+                    b => b,
+                };
+                m.range(map_bound(lo, float_key), hi)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // borrowed keys become shared, taken from strs so they are the rows' own strings
+    pub fn into_owned(self, strs: &mut Interner<'a>) -> Index<'static> {
+        let keys = match self.keys {
+            Keys::UInt(m) => Keys::UInt(m),
+            Keys::Int(m) => Keys::Int(m),
+            Keys::Boolean(m) => Keys::Boolean(m),
+            Keys::Str(m) => {
+                Keys::Str(m.map_keys(|k| {
+                    match k {
+                        StrKey::Borrowed(s) => StrKey::Shared(strs.get(s)),
+                        StrKey::Shared(s) => StrKey::Shared(s),
+                    }
+                }))
+            }
+            Keys::OwnedStr(m) => Keys::OwnedStr(m),
+            Keys::Float(m) => Keys::Float(m),
+            Keys::Text(m, t) => Keys::Text(m, t),
+            Keys::Tags(m) => Keys::Tags(m),
+            Keys::Dict(d) => Keys::Dict(d),
+        };
+        Index {
+            keys: keys,
+            nulls: self.nulls,
+            folded: self.folded,
+        }
+    }
+
     pub fn stats(&self) -> IndexStats {
-        let c = match self {
-            &Index::UInt(ref m) => m.len(),
-            &Index::Int(ref m) => m.len(),
-            &Index::Boolean(ref m) => m.len(),
-            &Index::Str(ref m) => m.len(),
-            &Index::OwnedStr(ref m) => m.len(),
+        let c = match self.keys {
+            Keys::UInt(ref m) => m.len(),
+            Keys::Int(ref m) => m.len(),
+            Keys::Boolean(ref m) => m.len(),
+            Keys::Str(ref m) => m.len(),
+            Keys::OwnedStr(ref m) => m.len(),
+            Keys::Float(ref m) => m.len(),
+            Keys::Text(ref m, _) => m.len(),
+            Keys::Tags(ref m) => m.len(),
+            Keys::Dict(ref d) => d.len(),
+        };
+        let (bytes, saved) = match (&self.keys, &self.folded) {
+            // the lowercased copies are paid for out of what the dictionary saves
+            (&Keys::Dict(ref d), &Some(ref f)) => {
+                let (bytes, saved) = d.stats();
+                (bytes, saved.saturating_sub(f.key_bytes()))
+            }
+            (&Keys::Dict(ref d), &None) => d.stats(),
+            _ => (0, 0),
         };
-        IndexStats { cardinality: c }
+        IndexStats {
+            cardinality: c,
+            nulls: self.nulls.len(),
+            string_bytes: bytes,
+            saved_bytes: saved,
+            case_folded: self.folded.is_some(),
+        }
+    }
+}
+
+// rows having every word of the text
+fn all_words(m: &KeyMap<String>, t: &Tokenizer, s: &str) -> RoaringBitmap<usize> {
+    let mut out: Option<RoaringBitmap<usize>> = None;
+    for w in t.tokens(s) {
+        match m.get(&w[..]) {
+            Some(b) => {
+                out = Some(match out {
+                    Some(o) => o & b,
+                    None => b.clone(),
+                });
+            }
+            None => return RoaringBitmap::new(),
+        }
     }
+    out.unwrap_or_else(RoaringBitmap::new)
+}
+
+fn any_tags(m: &KeyMap<Tag>, ts: &[Value]) -> RoaringBitmap<usize> {
+    let mut out = RoaringBitmap::new();
+    for t in ts.iter() {
+        if let Some(b) = m.get(&Tag::new(t)) {
+            out.union_with(b);
+        }
+    }
+    out
+}
+
+fn all_tags(m: &KeyMap<Tag>, ts: &[Value]) -> RoaringBitmap<usize> {
+    let mut out: Option<RoaringBitmap<usize>> = None;
+    for t in ts.iter() {
+        match m.get(&Tag::new(t)) {
+            Some(b) => {
+                out = Some(match out {
+                    Some(o) => o & b,
+                    None => b.clone(),
+                });
+            }
+            None => return RoaringBitmap::new(),
+        }
+    }
+    out.unwrap_or_else(RoaringBitmap::new)
+}
+
+fn remap_bitmap(b: &RoaringBitmap<usize>, ids: &[Option<usize>]) -> RoaringBitmap<usize> {
+    b.iter().filter_map(|id| ids[id]).collect()
+}
+
+fn uint_key(v: &Value) -> u64 {
+    match *v {
+        Value::UInt(u) => u,
+        _ => unreachable!(),
+    }
+}
+
+fn int_key(v: &Value) -> i64 {
+    match *v {
+        Value::Int(i) => i,
+        _ => unreachable!(),
+    }
+}
+
+fn float_key(v: &Value) -> FloatKey {
+    match *v {
+        Value::Float(x) => FloatKey::new(x),
+        _ => unreachable!(),
+    }
+}
+
+fn is_nan_bound(b: &Bound<&Value>) -> bool {
+    match *b {
+        Bound::Included(&Value::Float(x)) | Bound::Excluded(&Value::Float(x)) => x.is_nan(),
+        _ => false,
+    }
+}
+
+fn map_bound<'b, K, F>(b: Bound<&Value<'b>>, f: F) -> Bound<K>
+    where F: Fn(&Value<'b>) -> K
+{
+    match b {
+        Bound::Included(v) => Bound::Included(f(v)),
+        Bound::Excluded(v) => Bound::Excluded(f(v)),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+fn in_range<K: Ord>(k: &K, lo: &Bound<K>, hi: &Bound<K>) -> bool {
+    let above = match *lo {
+        Bound::Included(ref l) => k >= l,
+        Bound::Excluded(ref l) => k > l,
+        Bound::Unbounded => true,
+    };
+    let below = match *hi {
+        Bound::Included(ref h) => k <= h,
+        Bound::Excluded(ref h) => k < h,
+        Bound::Unbounded => true,
+    };
+    above && below
 }
 
-fn ensure_bitmap<T: Eq + Hash>(m: &mut HashMap<T, RoaringBitmap<usize>, BuildHasherDefault<FnvHasher>>, key: T) {
-    if let None = m.get(&key) {
-        let idx: RoaringBitmap<usize> = RoaringBitmap::new();
-        m.insert(key, idx);
+// BTreeMap::range panics on inverted bounds, so those are answered as empty up front
+fn is_empty_range<K: Ord>(lo: &Bound<K>, hi: &Bound<K>) -> bool {
+    match (lo, hi) {
+        (&Bound::Included(ref l), &Bound::Included(ref h)) => l.cmp(h) == Ordering::Greater,
+        (&Bound::Included(ref l), &Bound::Excluded(ref h)) |
+        (&Bound::Excluded(ref l), &Bound::Included(ref h)) |
+        (&Bound::Excluded(ref l), &Bound::Excluded(ref h)) => l.cmp(h) != Ordering::Less,
+        _ => false,
     }
 }
@@ -0,0 +1,168 @@
+extern crate serde;
+extern crate serde_json;
+
+use std::fmt;
+use std::sync::Arc;
+
+use self::serde::de::{Deserialize, Deserializer, MapAccess, Visitor};
+use self::serde_json::Number;
+pub use self::serde_json::Value as Json;
+
+use column::Column;
+use errs::Error;
+use value::Value;
+
+// the members of an object in the order written, a repeated key is kept twice so it can be
+// reported instead of the last one silently winning
+pub struct Members(pub Vec<(String, Json)>);
+
+struct MembersVisitor;
+
+impl<'de> Visitor<'de> for MembersVisitor {
+    type Value = Members;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a json object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Members, A::Error> {
+        let mut fields = Vec::new();
+        while let Some((k, v)) = try!(map.next_entry()) {
+            fields.push((k, v));
+        }
+        Ok(Members(fields))
+    }
+}
+
+impl<'de> Deserialize<'de> for Members {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Members, D::Error> {
+        d.deserialize_map(MembersVisitor)
+    }
+}
+
+// one line holding a json object
+pub fn parse_object(text: &str) -> Result<Members, Error> {
+    serde_json::from_str(text).map_err(|e| Error::Parse(e.to_string()))
+}
+
+fn kind(j: &Json) -> &'static str {
+    match *j {
+        Json::Null => "null",
+        Json::Bool(_) => "a boolean",
+        Json::Number(_) => "a number",
+        Json::String(_) => "a string",
+        Json::Array(_) => "an array",
+        Json::Object(_) => "an object",
+    }
+}
+
+fn expected(col: &Column) -> &'static str {
+    match *col {
+        Column::UInt => "an unsigned integer",
+        Column::Int => "an integer",
+        Column::Boolean => "a boolean",
+        Column::Float => "a number",
+        Column::Tags => "an array of strings and integers",
+        _ => "a string",
+    }
+}
+
+// why an integer column can not take this number
+fn not_integer(n: &Number) -> &'static str {
+    if n.is_f64() && n.as_f64().map_or(false, |x| x.fract() != 0.0) {
+        "a fractional number"
+    } else if n.is_i64() {
+        "a negative number"
+    } else {
+        "a number out of range"
+    }
+}
+
+// the value for column `i` of type `col`, strings come out owned, shared for a Str column
+pub fn to_value<'a>(col: &Column, nullable: bool, i: usize, j: &Json) -> Result<Value<'a>, Error> {
+    let wrong = |found| {
+        Error::WrongJsonType {
+            column: i,
+            expected: expected(col),
+            found: found,
+        }
+    };
+    match (col, j) {
+        (_, &Json::Null) if nullable => Ok(Value::Null),
+        (_, &Json::Null) => Err(Error::NotNullable(i)),
+        (&Column::UInt, &Json::Number(ref n)) => {
+            n.as_u64().map(Value::UInt).ok_or(wrong(not_integer(n)))
+        }
+        (&Column::Int, &Json::Number(ref n)) => {
+            n.as_i64().map(Value::Int).ok_or(wrong(not_integer(n)))
+        }
+        (&Column::Float, &Json::Number(ref n)) => {
+            n.as_f64().map(Value::Float).ok_or(wrong("a number out of range"))
+        }
+        (&Column::Boolean, &Json::Bool(b)) => Ok(Value::Boolean(b)),
+        (&Column::Str, &Json::String(ref s)) => Ok(Value::Shared(Arc::from(&s[..]))),
+        (&Column::OwnedStr, &Json::String(ref s)) | (&Column::Text, &Json::String(ref s)) => {
+            Ok(Value::OwnedStr(s.clone()))
+        }
+        // json numbers carry no integer type, a non negative one becomes a UInt tag and a
+        // negative one an Int tag. tags compare integers by value, so either still matches an
+        // Int tag of the same value. "7" stays a string tag
+        (&Column::Tags, &Json::Array(ref items)) => {
+            let mut ts = Vec::with_capacity(items.len());
+            for t in items.iter() {
+                match *t {
+                    Json::String(ref s) => ts.push(Value::OwnedStr(s.clone())),
+                    Json::Number(ref n) => {
+                        if let Some(u) = n.as_u64() {
+                            ts.push(Value::UInt(u));
+                        } else if let Some(v) = n.as_i64() {
+                            ts.push(Value::Int(v));
+                        } else {
+                            return Err(wrong("an array holding a non integer number"));
+                        }
+                    }
+                    _ => return Err(wrong("an array holding something else")),
+                }
+            }
+            Ok(Value::Tags(ts))
+        }
+        (_, j) => Err(wrong(kind(j))),
+    }
+}
+
+// floats json can not hold, nan and the infinities, become null
+pub fn from_value(v: &Value) -> Json {
+    match *v {
+        Value::UInt(u) => Json::from(u),
+        Value::Int(i) => Json::from(i),
+        Value::Boolean(b) => Json::Bool(b),
+        Value::Str(s) => Json::from(s),
+        Value::OwnedStr(ref s) => Json::from(&s[..]),
+        Value::Shared(ref s) => Json::from(&s[..]),
+        Value::Float(x) => Number::from_f64(x).map_or(Json::Null, Json::Number),
+        Value::Null => Json::Null,
+        Value::Tags(ref ts) => Json::Array(ts.iter().map(from_value).collect()),
+    }
+}
+
+// one object with a member per named column, unnamed columns have no key to be read back by
+// and are left out. the object map sorts its keys, so the members are written one by one to
+// keep them in column order
+pub fn write_row(out: &mut String, names: &[Option<String>], row: &[Value]) {
+    out.push('{');
+    let mut first = true;
+    for (name, v) in names.iter().zip(row.iter()) {
+        let name = match *name {
+            Some(ref name) => name,
+            None => continue,
+        };
+        if !first {
+            out.push(',');
+        }
+        first = false;
+        out.push_str(&Json::from(&name[..]).to_string());
+        out.push(':');
+        out.push_str(&from_value(v).to_string());
+    }
+    out.push('}');
+}
@@ -1,21 +1,40 @@
 extern crate fnv;
 extern crate roaring;
+extern crate serde;
+extern crate serde_json;
 
 mod errs;
 mod token;
+mod text;
 mod column;
 mod value;
 mod index;
 mod matches;
 mod pattern;
+mod persist;
+mod wal;
+mod csv;
+mod json;
 mod bucket;
 mod cache;
+mod row;
+mod typed;
 mod macros;
 
-pub use column::ColumnBuilder;
-pub use value::Value;
+pub use column::{ColumnBuilder, ColumnSpec};
+pub use value::{Interner, Value};
 pub use matches::Match;
 pub use pattern::Pattern;
 pub use bucket::{BucketBuilder, ReadHandle, WriteHandle};
-pub use cache::Cache;
+pub use cache::{Cache, SharedCache, Snapshot};
 pub use errs::Error;
+pub use wal::FsyncPolicy;
+pub use csv::CsvOptions;
+pub use row::{OxideRow, RowField, TagField};
+pub use typed::{Field, Filter, Ordinal, Rows, Scalar, TextField, Textual, TypedBucket};
+
+// what the code of #[derive(OxideRow)] builds its field handles with, not part of the api
+#[doc(hidden)]
+pub mod __private {
+    pub use typed::{field, text_field};
+}
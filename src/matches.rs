@@ -1,25 +1,60 @@
+use std::borrow::Cow;
+use std::collections::Bound;
 use std::convert;
 use std::slice::Iter;
+use std::vec;
 
 use value::Value;
 
 #[derive(Clone, Debug)]
 pub enum Match<'a> {
-    UInt(usize),
-    Int(isize),
+    UInt(u64),
+    Int(i64),
     Boolean(bool),
     Str(&'a str),
     OwnedStr(String),
+    Float(f64),
+    Range(Value<'a>, Value<'a>),
+    Gt(Value<'a>),
+    Ge(Value<'a>),
+    Lt(Value<'a>),
+    Le(Value<'a>),
+    Not(Value<'a>),
+    In(Vec<Value<'a>>),
+    Prefix(&'a str),
+    IgnoreCase(&'a str),
+    ContainsWord(&'a str),
+    ContainsAllWords(&'a str),
+    HasAny(Vec<Value<'a>>),
+    HasAll(Vec<Value<'a>>),
+    Null,
+    NotNull,
     Any,
 }
 
+impl<'a> Match<'a> {
+    // Range is inclusive on both ends, like sql between
+    pub fn bounds(&self) -> Option<(Bound<&Value<'a>>, Bound<&Value<'a>>)> {
+        match *self {
+            Match::Range(ref lo, ref hi) => Some((Bound::Included(lo), Bound::Included(hi))),
+            Match::Gt(ref v) => Some((Bound::Excluded(v), Bound::Unbounded)),
+            Match::Ge(ref v) => Some((Bound::Included(v), Bound::Unbounded)),
+            Match::Lt(ref v) => Some((Bound::Unbounded, Bound::Excluded(v))),
+            Match::Le(ref v) => Some((Bound::Unbounded, Bound::Included(v))),
+            _ => None,
+        }
+    }
+}
+
+// rows found, borrowed from the bucket unless it has dictionary columns whose codes had to
+// be turned back into strings
 #[derive(Debug)]
 pub struct MatchResults<'a, 'b: 'a> {
-    data: Vec<&'a [Value<'b>]>,
+    data: Vec<Cow<'a, [Value<'b>]>>,
 }
 
 impl<'a, 'b: 'a> MatchResults<'a, 'b> {
-    pub fn new(d: Vec<&'a [Value<'b>]>) -> Self {
+    pub fn new(d: Vec<Cow<'a, [Value<'b>]>>) -> Self {
         MatchResults {
             data: d
         }
@@ -33,23 +68,44 @@ impl<'a, 'b: 'a> MatchResults<'a, 'b> {
         self.data.len() == 0
     }
 
-    pub fn iter<'c>(&self) -> Iter<'c, &[Value<'b>]> {
+    pub fn iter<'s>(&'s self) -> Iter<'s, Cow<'a, [Value<'b>]>> {
         self.data.iter()
     }
 }
 
-impl<'a> convert::Into<Match<'a>> for usize {
+impl<'a, 'b: 'a> IntoIterator for MatchResults<'a, 'b> {
+    type Item = Cow<'a, [Value<'b>]>;
+    type IntoIter = vec::IntoIter<Cow<'a, [Value<'b>]>>;
+
+    fn into_iter(self) -> vec::IntoIter<Cow<'a, [Value<'b>]>> {
+        self.data.into_iter()
+    }
+}
+
+impl<'a> convert::Into<Match<'a>> for u64 {
     fn into(self) -> Match<'a> {
         Match::UInt(self)
     }
 }
 
-impl<'a> convert::Into<Match<'a>> for isize {
+impl<'a> convert::Into<Match<'a>> for usize {
+    fn into(self) -> Match<'a> {
+        Match::UInt(self as u64)
+    }
+}
+
+impl<'a> convert::Into<Match<'a>> for i64 {
     fn into(self) -> Match<'a> {
         Match::Int(self)
     }
 }
 
+impl<'a> convert::Into<Match<'a>> for isize {
+    fn into(self) -> Match<'a> {
+        Match::Int(self as i64)
+    }
+}
+
 impl<'a> convert::Into<Match<'a>> for bool {
     fn into(self) -> Match<'a> {
         Match::Boolean(self)
@@ -67,3 +123,9 @@ impl<'a> convert::Into<Match<'a>> for String {
         Match::OwnedStr(self)
     }
 }
+
+impl<'a> convert::Into<Match<'a>> for f64 {
+    fn into(self) -> Match<'a> {
+        Match::Float(self)
+    }
+}
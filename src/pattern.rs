@@ -2,12 +2,15 @@ use std::ops;
 
 use column::ColumnRef;
 use value::Value;
+use matches::Match;
 
 #[derive(Debug)]
 pub enum Pattern<'a> {
     Single(&'a ColumnRef, &'a Value<'a>),
+    Match(&'a ColumnRef, Match<'a>),
     And(Box<Pattern<'a>>, Box<Pattern<'a>>),
     Or(Box<Pattern<'a>>, Box<Pattern<'a>>),
+    Not(Box<Pattern<'a>>),
 }
 
 impl<'a> Pattern<'a> {
@@ -15,6 +18,62 @@ impl<'a> Pattern<'a> {
         Pattern::Single(refc, refv)
     }
 
+    pub fn range<T: Into<Value<'a>>>(refc: &'a ColumnRef, lo: T, hi: T) -> Pattern<'a> {
+        Pattern::Match(refc, Match::Range(lo.into(), hi.into()))
+    }
+
+    pub fn gt<T: Into<Value<'a>>>(refc: &'a ColumnRef, v: T) -> Pattern<'a> {
+        Pattern::Match(refc, Match::Gt(v.into()))
+    }
+
+    pub fn ge<T: Into<Value<'a>>>(refc: &'a ColumnRef, v: T) -> Pattern<'a> {
+        Pattern::Match(refc, Match::Ge(v.into()))
+    }
+
+    pub fn lt<T: Into<Value<'a>>>(refc: &'a ColumnRef, v: T) -> Pattern<'a> {
+        Pattern::Match(refc, Match::Lt(v.into()))
+    }
+
+    pub fn le<T: Into<Value<'a>>>(refc: &'a ColumnRef, v: T) -> Pattern<'a> {
+        Pattern::Match(refc, Match::Le(v.into()))
+    }
+
+    pub fn any_of(refc: &'a ColumnRef, vals: Vec<Value<'a>>) -> Pattern<'a> {
+        Pattern::Match(refc, Match::In(vals))
+    }
+
+    pub fn prefix(refc: &'a ColumnRef, p: &'a str) -> Pattern<'a> {
+        Pattern::Match(refc, Match::Prefix(p))
+    }
+
+    pub fn ignore_case(refc: &'a ColumnRef, s: &'a str) -> Pattern<'a> {
+        Pattern::Match(refc, Match::IgnoreCase(s))
+    }
+
+    pub fn contains_word(refc: &'a ColumnRef, w: &'a str) -> Pattern<'a> {
+        Pattern::Match(refc, Match::ContainsWord(w))
+    }
+
+    pub fn contains_all_words(refc: &'a ColumnRef, s: &'a str) -> Pattern<'a> {
+        Pattern::Match(refc, Match::ContainsAllWords(s))
+    }
+
+    pub fn has_any(refc: &'a ColumnRef, tags: Vec<Value<'a>>) -> Pattern<'a> {
+        Pattern::Match(refc, Match::HasAny(tags))
+    }
+
+    pub fn has_all(refc: &'a ColumnRef, tags: Vec<Value<'a>>) -> Pattern<'a> {
+        Pattern::Match(refc, Match::HasAll(tags))
+    }
+
+    pub fn null(refc: &'a ColumnRef) -> Pattern<'a> {
+        Pattern::Match(refc, Match::Null)
+    }
+
+    pub fn not_null(refc: &'a ColumnRef) -> Pattern<'a> {
+        Pattern::Match(refc, Match::NotNull)
+    }
+
     pub fn and(self, rhs: Pattern<'a>) -> Pattern<'a> {
         Pattern::And(Box::new(self), Box::new(rhs))
     }
@@ -22,6 +81,14 @@ impl<'a> Pattern<'a> {
     pub fn or(self, rhs: Pattern<'a>) -> Pattern<'a> {
         Pattern::Or(Box::new(self), Box::new(rhs))
     }
+
+    pub fn not(self) -> Pattern<'a> {
+        Pattern::Not(Box::new(self))
+    }
+
+    pub fn ne<T: Into<Value<'a>>>(refc: &'a ColumnRef, v: T) -> Pattern<'a> {
+        Pattern::Match(refc, Match::Not(v.into()))
+    }
 }
 
 impl<'a> ops::BitAnd for Pattern<'a> {
@@ -39,3 +106,11 @@ impl<'a> ops::BitOr for Pattern<'a> {
         Pattern::Or(Box::new(self), Box::new(rhs))
     }
 }
+
+impl<'a> ops::Not for Pattern<'a> {
+    type Output = Pattern<'a>;
+
+    fn not(self) -> Pattern<'a> {
+        Pattern::Not(Box::new(self))
+    }
+}
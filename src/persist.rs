@@ -0,0 +1,440 @@
+extern crate fnv;
+use fnv::FnvHasher;
+
+extern crate roaring;
+use roaring::RoaringBitmap;
+
+use std::hash::Hasher;
+use std::str;
+use std::sync::Arc;
+
+use errs::Error;
+use value::{Interner, Value};
+
+const MAGIC: &'static [u8] = b"OXDB";
+// files of any other version are turned down
+pub const VERSION: u32 = 1;
+
+// row ids are written the way roaring lays them out: split by their high bits into containers,
+// each holding the low 16 bits of its ids as a sorted array while it has at most ARRAY_MAX of
+// them and as a bitset of 1024 words beyond that
+const ARRAY_MAX: usize = 4096;
+
+// file layout: magic, version, body length, body, fnv checksum of the body.
+// every number is little endian, strings and lists are prefixed by their length
+pub struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        Writer { buf: Vec::new() }
+    }
+
+    pub fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    pub fn u16(&mut self, v: u16) {
+        self.buf.push(v as u8);
+        self.buf.push((v >> 8) as u8);
+    }
+
+    pub fn u64(&mut self, v: u64) {
+        for i in 0..8 {
+            self.buf.push((v >> (i * 8)) as u8);
+        }
+    }
+
+    pub fn usize(&mut self, v: usize) {
+        self.u64(v as u64)
+    }
+
+    pub fn bool(&mut self, v: bool) {
+        self.u8(v as u8)
+    }
+
+    pub fn f64(&mut self, v: f64) {
+        self.u64(v.to_bits())
+    }
+
+    pub fn str(&mut self, s: &str) {
+        self.usize(s.len());
+        self.buf.extend_from_slice(s.as_bytes());
+    }
+
+    pub fn ids(&mut self, b: &RoaringBitmap<usize>) {
+        self.containers(b.iter())
+    }
+
+    // same layout as ids, the ids must be sorted
+    pub fn id_list(&mut self, ids: &[usize]) {
+        self.containers(ids.iter().cloned())
+    }
+
+    fn containers<I: Iterator<Item = usize>>(&mut self, ids: I) {
+        let mut containers: Vec<(usize, Vec<u16>)> = Vec::new();
+        for id in ids {
+            let key = id >> 16;
+            if containers.last().map_or(true, |&(k, _)| k != key) {
+                containers.push((key, Vec::new()));
+            }
+            containers.last_mut().unwrap().1.push(id as u16);
+        }
+        self.usize(containers.len());
+        for (key, lows) in containers.into_iter() {
+            self.usize(key);
+            self.u16((lows.len() - 1) as u16);
+            if lows.len() <= ARRAY_MAX {
+                for low in lows.into_iter() {
+                    self.u16(low);
+                }
+            } else {
+                let mut words = [0u64; 1024];
+                for low in lows.into_iter() {
+                    words[low as usize >> 6] |= 1 << (low & 63);
+                }
+                for w in words.iter() {
+                    self.u64(*w);
+                }
+            }
+        }
+    }
+
+    pub fn values(&mut self, vals: &[Value]) {
+        self.usize(vals.len());
+        for v in vals.iter() {
+            self.value(v);
+        }
+    }
+
+    pub fn extend(&mut self, other: &Writer) {
+        self.buf.extend_from_slice(&other.buf);
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    pub fn value(&mut self, v: &Value) {
+        match *v {
+            Value::UInt(u) => {
+                self.u8(0);
+                self.u64(u);
+            }
+            Value::Int(i) => {
+                self.u8(1);
+                self.u64(i as u64);
+            }
+            Value::Boolean(b) => {
+                self.u8(2);
+                self.bool(b);
+            }
+            Value::Str(s) => {
+                self.u8(3);
+                self.str(s);
+            }
+            Value::OwnedStr(ref s) => {
+                self.u8(4);
+                self.str(s);
+            }
+            Value::Float(x) => {
+                self.u8(5);
+                self.f64(x);
+            }
+            Value::Tags(ref ts) => {
+                self.u8(6);
+                self.values(ts);
+            }
+            Value::Null => self.u8(7),
+            Value::Shared(ref s) => {
+                self.u8(8);
+                self.str(s);
+            }
+        }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.buf.len() + 24);
+        out.extend_from_slice(MAGIC);
+        for i in 0..4 {
+            out.push((VERSION >> (i * 8)) as u8);
+        }
+        let mut tail = Writer::new();
+        tail.usize(self.buf.len());
+        out.extend_from_slice(&tail.buf);
+        out.extend_from_slice(&self.buf);
+        let mut tail = Writer::new();
+        tail.u64(checksum(&self.buf));
+        out.extend_from_slice(&tail.buf);
+        out
+    }
+}
+
+// reads back what a Writer wrote, borrowed strings point into the buffer. shared strings read
+// again are handed the Arc of the first read, so rows that shared one before saving share it
+// after loading
+pub struct Reader<'c> {
+    buf: &'c [u8],
+    pos: usize,
+    strs: Interner<'c>,
+}
+
+impl<'c> Reader<'c> {
+    // a reader over raw bytes, without the file header
+    pub fn new(buf: &'c [u8]) -> Reader<'c> {
+        Reader {
+            buf: buf,
+            pos: 0,
+            strs: Interner::new(),
+        }
+    }
+
+    // checks the header and checksum, the reader is left at the start of the body
+    pub fn open(data: &'c [u8]) -> Result<Reader<'c>, Error> {
+        if data.len() < 16 || &data[..4] != MAGIC {
+            return Err(Error::Corrupt("not a snapshot file"));
+        }
+        let mut version = 0u32;
+        for i in 0..4 {
+            version |= (data[4 + i] as u32) << (i * 8);
+        }
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let mut head = Reader::new(&data[8..16]);
+        let len = try!(head.usize());
+        if data.len() - 16 < len || data.len() - 16 - len != 8 {
+            return Err(Error::Corrupt("truncated file"));
+        }
+        let body = &data[16..16 + len];
+        let mut tail = Reader::new(&data[16 + len..]);
+        if try!(tail.u64()) != checksum(body) {
+            return Err(Error::ChecksumMismatch);
+        }
+        Ok(Reader::new(body))
+    }
+
+    pub fn done(&self) -> bool {
+        self.pos == self.buf.len()
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'c [u8], Error> {
+        if self.buf.len() - self.pos < n {
+            return Err(Error::Corrupt("unexpected end of data"));
+        }
+        let s = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(s)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, Error> {
+        Ok(try!(self.take(1))[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, Error> {
+        let b = try!(self.take(2));
+        Ok(b[0] as u16 | (b[1] as u16) << 8)
+    }
+
+    pub fn u64(&mut self) -> Result<u64, Error> {
+        let b = try!(self.take(8));
+        let mut v = 0u64;
+        for i in 0..8 {
+            v |= (b[i] as u64) << (i * 8);
+        }
+        Ok(v)
+    }
+
+    pub fn usize(&mut self) -> Result<usize, Error> {
+        let v = try!(self.u64());
+        if v > usize::max_value() as u64 {
+            return Err(Error::Corrupt("length out of range"));
+        }
+        Ok(v as usize)
+    }
+
+    pub fn bool(&mut self) -> Result<bool, Error> {
+        match try!(self.u8()) {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Error::Corrupt("bad boolean")),
+        }
+    }
+
+    pub fn f64(&mut self) -> Result<f64, Error> {
+        Ok(f64::from_bits(try!(self.u64())))
+    }
+
+    pub fn str(&mut self) -> Result<&'c str, Error> {
+        let n = try!(self.usize());
+        let b = try!(self.take(n));
+        str::from_utf8(b).map_err(|_| Error::Corrupt("invalid utf-8 in string"))
+    }
+
+    pub fn string(&mut self) -> Result<String, Error> {
+        self.str().map(|s| s.to_owned())
+    }
+
+    // row ids, all of them below `rows`
+    pub fn ids(&mut self, rows: usize) -> Result<RoaringBitmap<usize>, Error> {
+        let n = try!(self.usize());
+        let mut b = RoaringBitmap::new();
+        let mut last = None;
+        for _ in 0..n {
+            let key = try!(self.usize());
+            if last.map_or(false, |l| key <= l) || key > rows >> 16 {
+                return Err(Error::Corrupt("row id out of range"));
+            }
+            last = Some(key);
+            let base = key << 16;
+            let card = try!(self.u16()) as usize + 1;
+            let mut ids = Vec::with_capacity(card);
+            if card <= ARRAY_MAX {
+                for _ in 0..card {
+                    let id = base | try!(self.u16()) as usize;
+                    if ids.last().map_or(false, |&l| id <= l) {
+                        return Err(Error::Corrupt("row ids out of order"));
+                    }
+                    ids.push(id);
+                }
+            } else {
+                for i in 0..1024 {
+                    let mut w = try!(self.u64());
+                    while w != 0 {
+                        ids.push(base | i << 6 | w.trailing_zeros() as usize);
+                        w &= w - 1;
+                    }
+                }
+                if ids.len() != card {
+                    return Err(Error::Corrupt("row id container does not add up"));
+                }
+            }
+            for id in ids.into_iter() {
+                if id >= rows {
+                    return Err(Error::Corrupt("row id out of range"));
+                }
+                b.insert(id);
+            }
+        }
+        Ok(b)
+    }
+
+    pub fn shared(&mut self) -> Result<Arc<str>, Error> {
+        let s = try!(self.str());
+        Ok(self.strs.get(s))
+    }
+
+    pub fn values(&mut self) -> Result<Vec<Value<'c>>, Error> {
+        let n = try!(self.usize());
+        let mut vals = Vec::new();
+        for _ in 0..n {
+            vals.push(try!(self.value()));
+        }
+        Ok(vals)
+    }
+
+    // tags only hold plain values, tags inside tags are rejected instead of read recursively
+    pub fn value(&mut self) -> Result<Value<'c>, Error> {
+        match try!(self.u8()) {
+            6 => {
+                let n = try!(self.usize());
+                let mut ts = Vec::new();
+                for _ in 0..n {
+                    match try!(self.u8()) {
+                        6 => return Err(Error::Corrupt("tags nested in tags")),
+                        tag => ts.push(try!(self.scalar(tag))),
+                    }
+                }
+                Ok(Value::Tags(ts))
+            }
+            tag => self.scalar(tag),
+        }
+    }
+
+    fn scalar(&mut self, tag: u8) -> Result<Value<'c>, Error> {
+        match tag {
+            0 => Ok(Value::UInt(try!(self.u64()))),
+            1 => Ok(Value::Int(try!(self.u64()) as i64)),
+            2 => Ok(Value::Boolean(try!(self.bool()))),
+            3 => Ok(Value::Str(try!(self.str()))),
+            4 => Ok(Value::OwnedStr(try!(self.string()))),
+            5 => Ok(Value::Float(try!(self.f64()))),
+            7 => Ok(Value::Null),
+            8 => Ok(Value::Shared(try!(self.shared()))),
+            _ => Err(Error::Corrupt("unknown value type")),
+        }
+    }
+}
+
+// index keys that can be written out and read back
+pub trait Persist<'c>: Sized {
+    fn save(&self, w: &mut Writer);
+    fn load(r: &mut Reader<'c>) -> Result<Self, Error>;
+}
+
+impl<'c> Persist<'c> for u64 {
+    fn save(&self, w: &mut Writer) {
+        w.u64(*self)
+    }
+
+    fn load(r: &mut Reader<'c>) -> Result<Self, Error> {
+        r.u64()
+    }
+}
+
+impl<'c> Persist<'c> for i64 {
+    fn save(&self, w: &mut Writer) {
+        w.u64(*self as u64)
+    }
+
+    fn load(r: &mut Reader<'c>) -> Result<Self, Error> {
+        r.u64().map(|v| v as i64)
+    }
+}
+
+impl<'c> Persist<'c> for bool {
+    fn save(&self, w: &mut Writer) {
+        w.bool(*self)
+    }
+
+    fn load(r: &mut Reader<'c>) -> Result<Self, Error> {
+        r.bool()
+    }
+}
+
+impl<'c> Persist<'c> for &'c str {
+    fn save(&self, w: &mut Writer) {
+        w.str(self)
+    }
+
+    fn load(r: &mut Reader<'c>) -> Result<Self, Error> {
+        r.str()
+    }
+}
+
+impl<'c> Persist<'c> for Arc<str> {
+    fn save(&self, w: &mut Writer) {
+        w.str(self)
+    }
+
+    fn load(r: &mut Reader<'c>) -> Result<Self, Error> {
+        r.shared()
+    }
+}
+
+impl<'c> Persist<'c> for String {
+    fn save(&self, w: &mut Writer) {
+        w.str(self)
+    }
+
+    fn load(r: &mut Reader<'c>) -> Result<Self, Error> {
+        r.string()
+    }
+}
+
+pub fn checksum(data: &[u8]) -> u64 {
+    let mut h = FnvHasher::default();
+    h.write(data);
+    h.finish()
+}
@@ -0,0 +1,237 @@
+use std::sync::Arc;
+
+use bucket::BucketBuilder;
+use column::{ColumnBuilder, ColumnSpec};
+use errs::Error;
+use value::Value;
+
+// a rust type standing for one row of a bucket, #[derive(OxideRow)] from oxide-derive writes it
+pub trait OxideRow<'a>: Sized {
+    // a struct of typed handles, one per field, to build a Filter with
+    type Fields;
+
+    fn fields() -> Self::Fields;
+
+    // a builder with a column per field, in field order
+    fn schema<T: Into<String>>(name: T) -> BucketBuilder;
+
+    // the values WriteHandle::insert takes
+    fn into_values(self) -> Vec<Value<'a>>;
+
+    // reads back a row of MatchResults
+    fn from_values(row: &[Value<'a>]) -> Result<Self, Error>;
+}
+
+// a type a row field can have, and the column it is stored in
+pub trait RowField<'a>: Sized {
+    // what a filter compares the field with, the type itself except for Option
+    type Inner;
+
+    fn column() -> ColumnSpec;
+    fn into_value(self) -> Value<'a>;
+    fn from_value(v: &Value<'a>) -> Option<Self>;
+}
+
+// field types a Vec field can hold as tags
+pub trait TagField<'a>: RowField<'a> {}
+
+impl<'a> RowField<'a> for u64 {
+    type Inner = Self;
+
+    fn column() -> ColumnSpec {
+        ColumnBuilder::UInt.into()
+    }
+
+    fn into_value(self) -> Value<'a> {
+        Value::UInt(self)
+    }
+
+    fn from_value(v: &Value<'a>) -> Option<Self> {
+        match *v {
+            Value::UInt(u) => Some(u),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> RowField<'a> for usize {
+    type Inner = Self;
+
+    fn column() -> ColumnSpec {
+        ColumnBuilder::UInt.into()
+    }
+
+    fn into_value(self) -> Value<'a> {
+        Value::UInt(self as u64)
+    }
+
+    fn from_value(v: &Value<'a>) -> Option<Self> {
+        match *v {
+            Value::UInt(u) if u <= usize::max_value() as u64 => Some(u as usize),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> RowField<'a> for i64 {
+    type Inner = Self;
+
+    fn column() -> ColumnSpec {
+        ColumnBuilder::Int.into()
+    }
+
+    fn into_value(self) -> Value<'a> {
+        Value::Int(self)
+    }
+
+    fn from_value(v: &Value<'a>) -> Option<Self> {
+        match *v {
+            Value::Int(i) => Some(i),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> RowField<'a> for bool {
+    type Inner = Self;
+
+    fn column() -> ColumnSpec {
+        ColumnBuilder::Boolean.into()
+    }
+
+    fn into_value(self) -> Value<'a> {
+        Value::Boolean(self)
+    }
+
+    fn from_value(v: &Value<'a>) -> Option<Self> {
+        match *v {
+            Value::Boolean(b) => Some(b),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> RowField<'a> for f64 {
+    type Inner = Self;
+
+    fn column() -> ColumnSpec {
+        ColumnBuilder::Float.into()
+    }
+
+    fn into_value(self) -> Value<'a> {
+        Value::Float(self)
+    }
+
+    fn from_value(v: &Value<'a>) -> Option<Self> {
+        match *v {
+            Value::Float(x) => Some(x),
+            _ => None,
+        }
+    }
+}
+
+// a shared string lives in its row, not for 'a, so it can not be lent as one. rows of an owned
+// cache read their strings into String or Arc<str> fields
+impl<'a> RowField<'a> for &'a str {
+    type Inner = Self;
+
+    fn column() -> ColumnSpec {
+        ColumnBuilder::Str.into()
+    }
+
+    fn into_value(self) -> Value<'a> {
+        Value::Str(self)
+    }
+
+    fn from_value(v: &Value<'a>) -> Option<Self> {
+        match *v {
+            Value::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+// also reads Text columns, which may hold either kind of string
+impl<'a> RowField<'a> for String {
+    type Inner = Self;
+
+    fn column() -> ColumnSpec {
+        ColumnBuilder::OwnedStr.into()
+    }
+
+    fn into_value(self) -> Value<'a> {
+        Value::OwnedStr(self)
+    }
+
+    fn from_value(v: &Value<'a>) -> Option<Self> {
+        v.as_str().map(|s| s.to_owned())
+    }
+}
+
+// the field type for an owned cache's strings, read without a copy when the row shares its
+// string. it also fits a Str column, see TypedBucket::new
+impl<'a> RowField<'a> for Arc<str> {
+    type Inner = Self;
+
+    fn column() -> ColumnSpec {
+        ColumnBuilder::OwnedStr.into()
+    }
+
+    fn into_value(self) -> Value<'a> {
+        Value::Shared(self)
+    }
+
+    fn from_value(v: &Value<'a>) -> Option<Self> {
+        match *v {
+            Value::Shared(ref s) => Some(s.clone()),
+            _ => v.as_str().map(Arc::from),
+        }
+    }
+}
+
+impl<'a> TagField<'a> for u64 {}
+impl<'a> TagField<'a> for i64 {}
+impl<'a> TagField<'a> for &'a str {}
+impl<'a> TagField<'a> for String {}
+impl<'a> TagField<'a> for Arc<str> {}
+
+impl<'a, T: RowField<'a>> RowField<'a> for Option<T> {
+    type Inner = T::Inner;
+
+    fn column() -> ColumnSpec {
+        T::column().nullable()
+    }
+
+    fn into_value(self) -> Value<'a> {
+        match self {
+            Some(v) => v.into_value(),
+            None => Value::Null,
+        }
+    }
+
+    fn from_value(v: &Value<'a>) -> Option<Self> {
+        match *v {
+            Value::Null => Some(None),
+            _ => T::from_value(v).map(Some),
+        }
+    }
+}
+
+impl<'a, T: TagField<'a>> RowField<'a> for Vec<T> {
+    type Inner = Self;
+
+    fn column() -> ColumnSpec {
+        ColumnBuilder::Tags.into()
+    }
+
+    fn into_value(self) -> Value<'a> {
+        Value::Tags(self.into_iter().map(RowField::into_value).collect())
+    }
+
+    fn from_value(v: &Value<'a>) -> Option<Self> {
+        match *v {
+            Value::Tags(ref ts) => ts.iter().map(T::from_value).collect(),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use std::collections::HashSet;
+
+// splits text into lowercased words on anything that is not alphanumeric
+#[derive(Debug, Clone)]
+pub struct Tokenizer {
+    stop_words: HashSet<String>,
+}
+
+impl Tokenizer {
+    pub fn new(stop_words: &[String]) -> Tokenizer {
+        Tokenizer { stop_words: stop_words.iter().map(|w| w.to_lowercase()).collect() }
+    }
+
+    pub fn normalize(&self, word: &str) -> Option<String> {
+        let w = word.to_lowercase();
+        if w.is_empty() || self.stop_words.contains(&w) {
+            None
+        } else {
+            Some(w)
+        }
+    }
+
+    // distinct words in the order they first appear, stop words removed
+    pub fn tokens(&self, text: &str) -> Vec<String> {
+        let mut seen = HashSet::new();
+        let mut out = Vec::new();
+        for w in text.split(|c: char| !c.is_alphanumeric()) {
+            if let Some(w) = self.normalize(w) {
+                if seen.insert(w.clone()) {
+                    out.push(w);
+                }
+            }
+        }
+        out
+    }
+}
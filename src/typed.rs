@@ -0,0 +1,365 @@
+use std::borrow::Cow;
+use std::cmp;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::sync::Arc;
+use std::vec;
+
+use bucket::{Bucket, WriteHandle};
+use column::{Column, ColumnBuilder, ColumnRef};
+use errs::Error;
+use matches::Match;
+use pattern::Pattern;
+use row::{OxideRow, RowField, TagField};
+use value::Value;
+
+// the column of a row type R holding T, what R::fields() hands out. for a nullable field T is
+// the type inside the Option
+pub struct Field<R, T> {
+    col: usize,
+    row: PhantomData<fn() -> (R, T)>,
+}
+
+// for the derive, fields come from R::fields() so their columns match R's schema. lib.rs
+// hands these to the generated code only, through a hidden module
+pub fn field<R, T>(col: usize) -> Field<R, T> {
+    Field::new(col)
+}
+
+pub fn text_field<R>(col: usize) -> TextField<R> {
+    TextField::new(col)
+}
+
+impl<R, T> Field<R, T> {
+    fn new(col: usize) -> Self {
+        Field {
+            col: col,
+            row: PhantomData,
+        }
+    }
+}
+
+impl<R, T> Clone for Field<R, T> {
+    fn clone(&self) -> Self {
+        Field::new(self.col)
+    }
+}
+
+impl<R, T> Copy for Field<R, T> {}
+
+// a Text column of R, only searched by words
+pub struct TextField<R> {
+    col: usize,
+    row: PhantomData<fn() -> R>,
+}
+
+impl<R> TextField<R> {
+    fn new(col: usize) -> Self {
+        TextField {
+            col: col,
+            row: PhantomData,
+        }
+    }
+}
+
+impl<R> Clone for TextField<R> {
+    fn clone(&self) -> Self {
+        TextField::new(self.col)
+    }
+}
+
+impl<R> Copy for TextField<R> {}
+
+// field types compared by value
+pub trait Scalar<'a>: RowField<'a> {
+    fn to_match(self) -> Match<'a>;
+}
+
+// field types range matches apply to
+pub trait Ordinal<'a>: Scalar<'a> {}
+
+// string field types, for prefix and case insensitive matches
+pub trait Textual<'a>: Scalar<'a> {}
+
+impl<'a> Scalar<'a> for u64 {
+    fn to_match(self) -> Match<'a> {
+        Match::UInt(self)
+    }
+}
+
+impl<'a> Scalar<'a> for usize {
+    fn to_match(self) -> Match<'a> {
+        Match::UInt(self as u64)
+    }
+}
+
+impl<'a> Scalar<'a> for i64 {
+    fn to_match(self) -> Match<'a> {
+        Match::Int(self)
+    }
+}
+
+impl<'a> Scalar<'a> for bool {
+    fn to_match(self) -> Match<'a> {
+        Match::Boolean(self)
+    }
+}
+
+impl<'a> Scalar<'a> for f64 {
+    fn to_match(self) -> Match<'a> {
+        Match::Float(self)
+    }
+}
+
+impl<'a> Scalar<'a> for &'a str {
+    fn to_match(self) -> Match<'a> {
+        Match::Str(self)
+    }
+}
+
+impl<'a> Scalar<'a> for String {
+    fn to_match(self) -> Match<'a> {
+        Match::OwnedStr(self)
+    }
+}
+
+// no Match holds a shared string, a list of one does
+impl<'a> Scalar<'a> for Arc<str> {
+    fn to_match(self) -> Match<'a> {
+        Match::In(vec![Value::Shared(self)])
+    }
+}
+
+impl<'a> Ordinal<'a> for u64 {}
+impl<'a> Ordinal<'a> for usize {}
+impl<'a> Ordinal<'a> for i64 {}
+impl<'a> Ordinal<'a> for f64 {}
+impl<'a> Textual<'a> for &'a str {}
+impl<'a> Textual<'a> for String {}
+impl<'a> Textual<'a> for Arc<str> {}
+
+// conditions on the fields of R, a row has to meet all of them. no condition finds every row
+pub struct Filter<'a, R> {
+    conds: Vec<(usize, Match<'a>)>,
+    row: PhantomData<fn() -> R>,
+}
+
+impl<'a, R> Filter<'a, R> {
+    pub fn new() -> Self {
+        Filter {
+            conds: Vec::new(),
+            row: PhantomData,
+        }
+    }
+
+    fn cond(mut self, col: usize, m: Match<'a>) -> Self {
+        self.conds.push((col, m));
+        self
+    }
+
+    pub fn eq<T: Scalar<'a>>(self, f: Field<R, T>, v: T) -> Self {
+        self.cond(f.col, v.to_match())
+    }
+
+    pub fn ne<T: Scalar<'a>>(self, f: Field<R, T>, v: T) -> Self {
+        self.cond(f.col, Match::Not(v.into_value()))
+    }
+
+    pub fn one_of<T: Scalar<'a>>(self, f: Field<R, T>, vs: Vec<T>) -> Self {
+        self.cond(f.col, Match::In(vs.into_iter().map(RowField::into_value).collect()))
+    }
+
+    pub fn gt<T: Ordinal<'a>>(self, f: Field<R, T>, v: T) -> Self {
+        self.cond(f.col, Match::Gt(v.into_value()))
+    }
+
+    pub fn ge<T: Ordinal<'a>>(self, f: Field<R, T>, v: T) -> Self {
+        self.cond(f.col, Match::Ge(v.into_value()))
+    }
+
+    pub fn lt<T: Ordinal<'a>>(self, f: Field<R, T>, v: T) -> Self {
+        self.cond(f.col, Match::Lt(v.into_value()))
+    }
+
+    pub fn le<T: Ordinal<'a>>(self, f: Field<R, T>, v: T) -> Self {
+        self.cond(f.col, Match::Le(v.into_value()))
+    }
+
+    // inclusive on both ends
+    pub fn between<T: Ordinal<'a>>(self, f: Field<R, T>, lo: T, hi: T) -> Self {
+        self.cond(f.col, Match::Range(lo.into_value(), hi.into_value()))
+    }
+
+    pub fn prefix<T: Textual<'a>>(self, f: Field<R, T>, p: &'a str) -> Self {
+        self.cond(f.col, Match::Prefix(p))
+    }
+
+    pub fn ignore_case<T: Textual<'a>>(self, f: Field<R, T>, s: &'a str) -> Self {
+        self.cond(f.col, Match::IgnoreCase(s))
+    }
+
+    pub fn is_null<T>(self, f: Field<R, T>) -> Self {
+        self.cond(f.col, Match::Null)
+    }
+
+    pub fn not_null<T>(self, f: Field<R, T>) -> Self {
+        self.cond(f.col, Match::NotNull)
+    }
+
+    pub fn has_any<T: TagField<'a>>(self, f: Field<R, Vec<T>>, tags: Vec<T>) -> Self {
+        self.cond(f.col, Match::HasAny(tags.into_iter().map(RowField::into_value).collect()))
+    }
+
+    pub fn has_all<T: TagField<'a>>(self, f: Field<R, Vec<T>>, tags: Vec<T>) -> Self {
+        self.cond(f.col, Match::HasAll(tags.into_iter().map(RowField::into_value).collect()))
+    }
+
+    pub fn contains_word(self, f: TextField<R>, w: &'a str) -> Self {
+        self.cond(f.col, Match::ContainsWord(w))
+    }
+
+    pub fn contains_all_words(self, f: TextField<R>, s: &'a str) -> Self {
+        self.cond(f.col, Match::ContainsAllWords(s))
+    }
+}
+
+// a bucket seen through its row type. H is any handle on the bucket: a ReadHandle, a WriteHandle
+// or a Snapshot, writes need a WriteHandle
+pub struct TypedBucket<H, R> {
+    h: H,
+    row: PhantomData<fn() -> R>,
+}
+
+impl<'b, H, R> TypedBucket<H, R>
+    where H: Deref<Target = Bucket<'b>>,
+          R: OxideRow<'b>
+{
+    // fails unless the columns have the types and nullability of R's fields, in the same order.
+    // with more or fewer columns than fields the error names the first one the other side lacks.
+    // a String or Arc<str> field also fits a Str column, as an owned cache has them
+    pub fn new(h: H) -> Result<Self, Error> {
+        let want = R::schema("").columns;
+        {
+            let have = h.schema();
+            if want.len() != have.len() {
+                return Err(Error::SchemaMismatch(cmp::min(want.len(), have.len())));
+            }
+            for (i, (w, c)) in want.iter().zip(have.iter()).enumerate() {
+                let owned_on_str = w.kind == ColumnBuilder::OwnedStr &&
+                                   c.kind == ColumnBuilder::Str;
+                if (w.kind != c.kind && !owned_on_str) || w.nullable != c.nullable {
+                    return Err(Error::SchemaMismatch(i));
+                }
+            }
+        }
+        Ok(TypedBucket {
+            h: h,
+            row: PhantomData,
+        })
+    }
+
+    pub fn rows(&self) -> usize {
+        self.h.rows()
+    }
+
+    pub fn find<'f>(&self, f: &Filter<'f, R>) -> Result<Vec<R>, Error> {
+        let refs = try!(self.refs(f));
+        match try!(self.h.find_pattern(&pattern(&refs, f))) {
+            Some(res) => res.iter().map(|row| R::from_values(row)).collect(),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    // every live row in row id order, a row stored through the untyped api may not read as R
+    pub fn iter<'t>(&'t self) -> Rows<'t, 'b, R> {
+        Rows {
+            rows: self.h.all_rows().into_iter(),
+            row: PhantomData,
+        }
+    }
+
+    // a column ref per condition, or one to match every row against
+    fn refs<'f>(&self, f: &Filter<'f, R>) -> Result<Vec<ColumnRef>, Error> {
+        if f.conds.is_empty() {
+            return self.column_ref(0).map(|r| vec![r]);
+        }
+        f.conds.iter().map(|&(col, _)| self.column_ref(col)).collect()
+    }
+
+    fn column_ref(&self, col: usize) -> Result<ColumnRef, Error> {
+        self.h.get_column_ref(col).ok_or(Error::NoSuchColumn(col))
+    }
+
+    fn values(&self, r: R) -> Vec<Value<'b>> {
+        let specs = self.h.schema();
+        r.into_values()
+         .into_iter()
+         .zip(specs.iter())
+         .map(|(v, cs)| share(&cs.kind.column(), v))
+         .collect()
+    }
+}
+
+// a Str column takes no owned strings, those of a String field on one go in shared
+fn share<'v>(col: &Column, v: Value<'v>) -> Value<'v> {
+    match (col, v) {
+        (&Column::Str, Value::OwnedStr(s)) => Value::Shared(Arc::from(s)),
+        (_, v) => v,
+    }
+}
+
+// and are compared as shared strings
+fn share_match<'v>(col: &Column, m: &Match<'v>) -> Match<'v> {
+    match (col, m) {
+        (&Column::Str, &Match::OwnedStr(ref s)) => {
+            Match::In(vec![Value::Shared(Arc::from(&s[..]))])
+        }
+        (&Column::Str, &Match::Not(ref v)) => Match::Not(share(col, v.clone())),
+        (&Column::Str, &Match::In(ref vs)) => {
+            Match::In(vs.iter().map(|v| share(col, v.clone())).collect())
+        }
+        _ => m.clone(),
+    }
+}
+
+fn pattern<'p, R>(refs: &'p [ColumnRef], f: &Filter<'p, R>) -> Pattern<'p> {
+    let mut conds = refs.iter().zip(f.conds.iter());
+    let mut p = match conds.next() {
+        Some((r, &(_, ref m))) => Pattern::Match(r, share_match(&r.r, m)),
+        None => Pattern::Match(&refs[0], Match::Any),
+    };
+    for (r, &(_, ref m)) in conds {
+        p = p & Pattern::Match(r, share_match(&r.r, m));
+    }
+    p
+}
+
+impl<'a, 'b, R: OxideRow<'b>> TypedBucket<WriteHandle<'a, 'b>, R> {
+    pub fn insert(&mut self, r: R) -> Result<(), Error> {
+        let vals = self.values(r);
+        self.h.insert(vals)
+    }
+
+    pub fn upsert(&mut self, r: R) -> Result<bool, Error> {
+        let vals = self.values(r);
+        self.h.upsert(vals)
+    }
+
+    pub fn delete<'f>(&mut self, f: &Filter<'f, R>) -> Result<usize, Error> {
+        let refs = try!(self.refs(f));
+        self.h.delete_pattern(&pattern(&refs, f))
+    }
+}
+
+pub struct Rows<'t, 'b: 't, R> {
+    rows: vec::IntoIter<Cow<'t, [Value<'b>]>>,
+    row: PhantomData<fn() -> R>,
+}
+
+impl<'t, 'b: 't, R: OxideRow<'b>> Iterator for Rows<'t, 'b, R> {
+    type Item = Result<R, Error>;
+
+    fn next(&mut self) -> Option<Result<R, Error>> {
+        self.rows.next().map(|row| R::from_values(&row))
+    }
+}
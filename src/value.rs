@@ -1,15 +1,65 @@
+use std::collections::HashMap;
 use std::convert;
 use std::fmt;
+use std::mem;
+use std::sync::Arc;
 
 use errs::Error;
 
 #[derive(Clone, Debug)]
 pub enum Value<'a> {
-    UInt(usize),
-    Int(isize),
+    UInt(u64),
+    Int(i64),
     Boolean(bool),
     Str(&'a str),
     OwnedStr(String),
+    // a string shared by reference count, any string column takes it and it needs no lifetime
+    Shared(Arc<str>),
+    Float(f64),
+    Tags(Vec<Value<'a>>),
+    Null,
+}
+
+impl<'a> Value<'a> {
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Value::Str(s) => Some(s),
+            Value::OwnedStr(ref s) => Some(s),
+            Value::Shared(ref s) => Some(s),
+            _ => None,
+        }
+    }
+
+    // the same value without the borrow, borrowed strings become shared ones
+    pub fn into_owned(self, strs: &mut Interner<'a>) -> Value<'static> {
+        match self {
+            Value::UInt(u) => Value::UInt(u),
+            Value::Int(i) => Value::Int(i),
+            Value::Boolean(b) => Value::Boolean(b),
+            Value::Str(s) => Value::Shared(strs.get(s)),
+            Value::OwnedStr(s) => Value::OwnedStr(s),
+            Value::Shared(s) => Value::Shared(s),
+            Value::Float(x) => Value::Float(x),
+            Value::Tags(ts) => Value::Tags(ts.into_iter().map(|t| t.into_owned(strs)).collect()),
+            Value::Null => Value::Null,
+        }
+    }
+}
+
+// hands out one Arc per distinct borrowed string, so rows and index keys holding the same
+// string share it
+pub struct Interner<'a> {
+    strs: HashMap<&'a str, Arc<str>>,
+}
+
+impl<'a> Interner<'a> {
+    pub fn new() -> Self {
+        Interner { strs: HashMap::new() }
+    }
+
+    pub fn get(&mut self, s: &'a str) -> Arc<str> {
+        self.strs.entry(s).or_insert_with(|| Arc::from(s)).clone()
+    }
 }
 
 impl<'a> fmt::Display for Value<'a> {
@@ -20,60 +70,221 @@ impl<'a> fmt::Display for Value<'a> {
             &Value::Boolean(b) => write!(f, "{}", b),
             &Value::Str(s) => write!(f, "{}", s),
             &Value::OwnedStr(ref s) => write!(f, "{}", s),
+            &Value::Shared(ref s) => write!(f, "{}", s),
+            &Value::Float(x) => write!(f, "{}", x),
+            &Value::Tags(ref ts) => {
+                try!(write!(f, "["));
+                for (i, t) in ts.iter().enumerate() {
+                    if i > 0 {
+                        try!(write!(f, ", "));
+                    }
+                    try!(write!(f, "{}", t));
+                }
+                write!(f, "]")
+            }
+            &Value::Null => write!(f, "null"),
         }
     }
 }
 
+// the code a dictionary column's cell holds for null
+pub const NULL_CODE: u32 = u32::MAX;
+
+// rows per chunk, a chunk is shared between copies of the store until one of them writes to it
+const CHUNK: usize = 1024;
+
+// the rows, one after the other in chunks of CHUNK rows. the cells of dictionary columns are
+// codes kept apart from the values, 4 bytes a row, the column's index turns them back into strings
+#[derive(Clone)]
 pub struct ValueStore<'v> {
-    vals: Vec<Value<'v>>,
-    width: usize,
+    vals: Vec<Arc<Vec<Value<'v>>>>,
+    codes: Vec<Arc<Vec<u32>>>,
+    // per column, where a row keeps its cell
+    cells: Vec<Cell>,
+    plain: usize,
+    coded: usize,
     next_id: usize,
 }
 
+#[derive(Clone, Copy)]
+enum Cell {
+    Value(usize),
+    Code(usize),
+}
+
 impl<'v> ValueStore<'v> {
-    pub fn new(width: usize) -> Self {
+    // coded has a flag per column, set for the columns holding codes
+    pub fn new(coded: &[bool]) -> Self {
+        let mut cells = Vec::with_capacity(coded.len());
+        let (mut plain, mut codes) = (0, 0);
+        for &c in coded.iter() {
+            if c {
+                cells.push(Cell::Code(codes));
+                codes += 1;
+            } else {
+                cells.push(Cell::Value(plain));
+                plain += 1;
+            }
+        }
         ValueStore {
             vals: Vec::new(),
-            width: width,
+            codes: Vec::new(),
+            cells: cells,
+            plain: plain,
+            coded: codes,
             next_id: 0,
         }
     }
 
-    pub fn width(&self) -> usize {
-        self.width
-    }
-
     pub fn next_id(&self) -> usize {
         self.next_id
     }
 
-    pub fn slice_at(&self, start: usize, end: usize) -> &[Value] {
-        &self.vals[start..end]
+    pub fn has_codes(&self) -> bool {
+        self.coded > 0
+    }
+
+    // the values of a row, which are the whole row unless some columns hold codes
+    pub fn values(&self, id: usize) -> &[Value<'v>] {
+        let at = id % CHUNK * self.plain;
+        &self.vals[id / CHUNK][at..at + self.plain]
+    }
+
+    // the value in a column not holding codes
+    pub fn get(&self, id: usize, col: usize) -> &Value<'v> {
+        match self.cells[col] {
+            Cell::Value(i) => &self.vals[id / CHUNK][id % CHUNK * self.plain + i],
+            Cell::Code(_) => panic!("column {} holds codes", col),
+        }
+    }
+
+    // the code in a column holding codes, None for other columns
+    pub fn code(&self, id: usize, col: usize) -> Option<u32> {
+        match self.cells[col] {
+            Cell::Value(_) => None,
+            Cell::Code(i) => Some(self.codes[id / CHUNK][id % CHUNK * self.coded + i]),
+        }
+    }
+
+    // code is the cell of a column holding codes, which drops val. the replaced value comes
+    // back, or null for a code
+    pub fn set(&mut self, id: usize, col: usize, val: Value<'v>, code: Option<u32>) -> Value<'v> {
+        match self.cells[col] {
+            Cell::Value(i) => {
+                let chunk = Arc::make_mut(&mut self.vals[id / CHUNK]);
+                mem::replace(&mut chunk[id % CHUNK * self.plain + i], val)
+            }
+            Cell::Code(i) => {
+                let chunk = Arc::make_mut(&mut self.codes[id / CHUNK]);
+                chunk[id % CHUNK * self.coded + i] = code.unwrap();
+                Value::Null
+            }
+        }
     }
 
-    pub fn insert(&mut self, vals: &Vec<Value<'v>>) -> Result<(), Error> {
-        self.vals.extend(vals.iter().cloned());
+    // codes has one entry per column like vals, the code for the columns holding codes
+    pub fn insert(&mut self, vals: Vec<Value<'v>>, codes: &[Option<u32>]) -> Result<(), Error> {
+        let cells = self.cells.clone();
+        {
+            let (plain, coded) = self.last_chunks();
+            for ((v, &cell), &code) in vals.into_iter().zip(cells.iter()).zip(codes.iter()) {
+                match cell {
+                    Cell::Value(_) => plain.push(v),
+                    Cell::Code(_) => coded.push(code.unwrap()),
+                }
+            }
+        }
         self.next_id += 1;
         Ok(())
     }
 
+    // the chunks the next row goes in, a new pair once the last ones are full
+    fn last_chunks(&mut self) -> (&mut Vec<Value<'v>>, &mut Vec<u32>) {
+        if self.next_id % CHUNK == 0 {
+            self.vals.push(Arc::new(Vec::with_capacity(CHUNK * self.plain)));
+            self.codes.push(Arc::new(Vec::with_capacity(CHUNK * self.coded)));
+        }
+        (Arc::make_mut(self.vals.last_mut().unwrap()),
+         Arc::make_mut(self.codes.last_mut().unwrap()))
+    }
+
     pub fn rows(&self) -> usize {
-        self.vals.len() / self.width
+        self.next_id
+    }
+
+    // drops the last row
+    pub fn pop(&mut self) {
+        self.next_id -= 1;
+        let at = self.next_id % CHUNK;
+        if at == 0 {
+            self.vals.pop();
+            self.codes.pop();
+        } else {
+            Arc::make_mut(self.vals.last_mut().unwrap()).truncate(at * self.plain);
+            Arc::make_mut(self.codes.last_mut().unwrap()).truncate(at * self.coded);
+        }
+    }
+
+    // keeps only the rows in `keep`, in id order, so row n of the result is the nth kept row
+    pub fn retain(&mut self, keep: &[bool]) {
+        let vals = mem::replace(&mut self.vals, Vec::new());
+        let codes = mem::replace(&mut self.codes, Vec::new());
+        let (plain, coded, rows) = (self.plain, self.coded, self.next_id);
+        self.next_id = 0;
+        for id in 0..rows {
+            if keep[id] {
+                let (c, at) = (id / CHUNK, id % CHUNK);
+                {
+                    let (to_vals, to_codes) = self.last_chunks();
+                    to_vals.extend(vals[c][at * plain..(at + 1) * plain].iter().cloned());
+                    to_codes.extend_from_slice(&codes[c][at * coded..(at + 1) * coded]);
+                }
+                self.next_id += 1;
+            }
+        }
+    }
+
+    pub fn into_owned(self, strs: &mut Interner<'v>) -> ValueStore<'static> {
+        let mut vals = Vec::with_capacity(self.vals.len());
+        for chunk in self.vals.into_iter() {
+            let chunk = Arc::try_unwrap(chunk).unwrap_or_else(|c| (*c).clone());
+            vals.push(Arc::new(chunk.into_iter().map(|v| v.into_owned(strs)).collect()));
+        }
+        ValueStore {
+            vals: vals,
+            codes: self.codes,
+            cells: self.cells,
+            plain: self.plain,
+            coded: self.coded,
+            next_id: self.next_id,
+        }
     }
 }
 
-impl<'a> convert::Into<Value<'a>> for usize {
+impl<'a> convert::Into<Value<'a>> for u64 {
     fn into(self) -> Value<'a> {
         Value::UInt(self)
     }
 }
 
-impl<'a> convert::Into<Value<'a>> for isize {
+impl<'a> convert::Into<Value<'a>> for usize {
+    fn into(self) -> Value<'a> {
+        Value::UInt(self as u64)
+    }
+}
+
+impl<'a> convert::Into<Value<'a>> for i64 {
     fn into(self) -> Value<'a> {
         Value::Int(self)
     }
 }
 
+impl<'a> convert::Into<Value<'a>> for isize {
+    fn into(self) -> Value<'a> {
+        Value::Int(self as i64)
+    }
+}
+
 impl<'a> convert::Into<Value<'a>> for bool {
     fn into(self) -> Value<'a> {
         Value::Boolean(self)
@@ -91,3 +302,30 @@ impl<'a> convert::Into<Value<'a>> for String {
         Value::OwnedStr(self)
     }
 }
+
+impl<'a> convert::Into<Value<'a>> for Arc<str> {
+    fn into(self) -> Value<'a> {
+        Value::Shared(self)
+    }
+}
+
+impl<'a> convert::Into<Value<'a>> for f64 {
+    fn into(self) -> Value<'a> {
+        Value::Float(self)
+    }
+}
+
+impl<'a, T: Into<Value<'a>>> convert::Into<Value<'a>> for Option<T> {
+    fn into(self) -> Value<'a> {
+        match self {
+            Some(v) => v.into(),
+            None => Value::Null,
+        }
+    }
+}
+
+impl<'a, T: Into<Value<'a>>> convert::Into<Value<'a>> for Vec<T> {
+    fn into(self) -> Value<'a> {
+        Value::Tags(self.into_iter().map(|t| t.into()).collect())
+    }
+}
@@ -0,0 +1,150 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use errs::Error;
+use persist::{checksum, Reader, Writer, VERSION};
+
+// when appended records are forced to disk, anything not synced yet can be lost with the machine
+#[derive(Debug, Clone, Copy)]
+pub enum FsyncPolicy {
+    Always,
+    Every(usize),
+    Never,
+}
+
+// kinds of log records, bucket ops carry the ops of one write or one committed transaction
+pub const RECORD_OPS: u8 = 0;
+pub const RECORD_NEW_BUCKET: u8 = 1;
+pub const RECORD_DROP_BUCKET: u8 = 2;
+
+const MAGIC: &'static [u8] = b"OXWL";
+// magic and the format version of every record in the log
+const HEADER: usize = 8;
+
+// an append only file of records after a header, each one framed as length, body, fnv
+// checksum of the body
+pub struct Wal {
+    file: File,
+    policy: FsyncPolicy,
+    unsynced: usize,
+    lsn: u64,
+}
+
+impl Wal {
+    // valid is how many bytes of the existing file replay accepted, a torn tail past it is cut off.
+    // lsn is the sequence number of the last record applied
+    pub fn open<P: AsRef<Path>>(path: P,
+                                valid: usize,
+                                lsn: u64,
+                                policy: FsyncPolicy)
+                                -> Result<Wal, Error> {
+        let file = try!(OpenOptions::new().create(true).append(true).open(path));
+        let mut wal = Wal {
+            file: file,
+            policy: policy,
+            unsynced: 0,
+            lsn: lsn,
+        };
+        if valid == 0 {
+            try!(wal.truncate());
+        } else if try!(wal.file.metadata()).len() > valid as u64 {
+            try!(wal.file.set_len(valid as u64));
+            try!(wal.file.sync_all());
+        }
+        Ok(wal)
+    }
+
+    pub fn lsn(&self) -> u64 {
+        self.lsn
+    }
+
+    // the record body goes out behind the next sequence number
+    pub fn append(&mut self, rec: &Writer) -> Result<(), Error> {
+        let mut body = Writer::new();
+        body.u64(self.lsn + 1);
+        body.extend(rec);
+        let body = body.bytes();
+        let mut frame = Writer::new();
+        frame.usize(body.len());
+        let mut out = frame.bytes().to_vec();
+        out.extend_from_slice(body);
+        let mut sum = Writer::new();
+        sum.u64(checksum(body));
+        out.extend_from_slice(sum.bytes());
+        try!(self.file.write_all(&out));
+        self.lsn += 1;
+        self.unsynced += 1;
+        let sync = match self.policy {
+            FsyncPolicy::Always => true,
+            FsyncPolicy::Every(n) => self.unsynced >= n,
+            FsyncPolicy::Never => false,
+        };
+        if sync {
+            try!(self.file.sync_data());
+            self.unsynced = 0;
+        }
+        Ok(())
+    }
+
+    // everything logged so far is in a snapshot now, the log starts over
+    pub fn truncate(&mut self) -> Result<(), Error> {
+        try!(self.file.set_len(0));
+        let mut header = MAGIC.to_vec();
+        for i in 0..4 {
+            header.push((VERSION >> (i * 8)) as u8);
+        }
+        try!(self.file.write_all(&header));
+        try!(self.file.sync_all());
+        self.unsynced = 0;
+        Ok(())
+    }
+}
+
+// the complete records of the log in data and how many bytes they take up along with the header
+pub fn read<'c>(data: &'c [u8]) -> Result<(Vec<Reader<'c>>, usize), Error> {
+    // too short for a header, so nothing was ever appended
+    if data.len() < HEADER {
+        return Ok((Vec::new(), 0));
+    }
+    if &data[..4] != MAGIC {
+        return Err(Error::Corrupt("not a log file"));
+    }
+    let mut version = 0u32;
+    for i in 0..4 {
+        version |= (data[4 + i] as u32) << (i * 8);
+    }
+    if version != VERSION {
+        return Err(Error::UnsupportedVersion(version));
+    }
+    let (rs, valid) = try!(records(&data[HEADER..]));
+    Ok((rs, HEADER + valid))
+}
+
+// the bodies of the complete records in data, each starting with its sequence number, and how
+// many bytes they take up. a crash mid append only leaves a torn last record: one cut short, or
+// one that fills the rest of the log but fails its checksum. it is left out, while a record
+// failing its checksum with more behind it means the log is damaged
+fn records<'c>(data: &'c [u8]) -> Result<(Vec<Reader<'c>>, usize), Error> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    loop {
+        let rest = &data[pos..];
+        if rest.len() < 16 {
+            break;
+        }
+        let len = match Reader::new(&rest[..8]).usize() {
+            Ok(len) if rest.len() - 16 >= len => len,
+            _ => break,
+        };
+        let body = &rest[8..8 + len];
+        match Reader::new(&rest[8 + len..16 + len]).u64() {
+            Ok(sum) if sum == checksum(body) => {}
+            _ if rest.len() == 16 + len => break,
+            _ => return Err(Error::Corrupt("a log record before the end fails its checksum")),
+        }
+        out.push(Reader::new(body));
+        pos += 16 + len;
+    }
+    Ok((out, pos))
+}
@@ -1,5 +1,7 @@
 #[macro_use]
 extern crate oxide;
+#[macro_use]
+extern crate oxide_derive;
 
 fn new_cache_with_bucket(name: &str) -> oxide::Cache {
     let mut c = oxide::Cache::new();
@@ -11,6 +13,29 @@ fn new_cache_with_bucket(name: &str) -> oxide::Cache {
     c
 }
 
+// a directory of the test's own under the temp dir, removed with everything in it once the
+// test is over, passed or not
+struct TestDir(std::path::PathBuf);
+
+impl TestDir {
+    fn new(test: &str) -> TestDir {
+        let p = std::env::temp_dir().join(format!("oxide-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&p);
+        std::fs::create_dir_all(&p).unwrap();
+        TestDir(p)
+    }
+
+    fn join<P: AsRef<std::path::Path>>(&self, file: P) -> std::path::PathBuf {
+        self.0.join(file)
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
 #[test]
 fn create_bucket() {
     let c = new_cache_with_bucket("foo");
@@ -632,3 +657,2999 @@ fn delete_pattern() {
 
     });
 }
+
+#[test]
+fn find_range() {
+    let n = "foo";
+    let mut c = oxide::Cache::new();
+    {
+        let mut bb = oxide::BucketBuilder::new(n);
+        bb = bb.add_column(oxide::ColumnBuilder::Int.ordered());
+        bb = bb.add_column(oxide::ColumnBuilder::UInt);
+        bb = bb.add_column(oxide::ColumnBuilder::Str);
+        c.new_bucket(bb).unwrap();
+    }
+
+    c.bucket_mut(n, |w| {
+        let mut w = w.unwrap();
+        w.insert(vals![-20isize, 10usize, "a"]).unwrap();
+        w.insert(vals![-5isize, 20usize, "b"]).unwrap();
+        w.insert(vals![0isize, 30usize, "a"]).unwrap();
+        w.insert(vals![5isize, 40usize, "b"]).unwrap();
+        w.insert(vals![20isize, 50usize, "a"]).unwrap();
+    });
+
+    c.bucket(n, |r| {
+        let r = r.unwrap();
+
+        use oxide::{Match, Value};
+        // ordered index, negatives sort before positives
+        let m = vec![Match::Range(Value::Int(-10), Value::Int(5)), Match::Any, Match::Any];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(3, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let m = vec![Match::Lt(Value::Int(0)), Match::Any, Match::Str("a")];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(1, res.len());
+        } else {
+            assert!(false);
+        }
+
+        // hashed index still answers ranges
+        let m = vec![Match::Any, Match::Gt(Value::UInt(30)), Match::Any];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(2, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let m = vec![Match::Any, Match::Ge(Value::UInt(30)), Match::Any];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(3, res.len());
+        } else {
+            assert!(false);
+        }
+
+        // inverted range matches nothing
+        let m = vec![Match::Range(Value::Int(5), Value::Int(-5)), Match::Any, Match::Any];
+        if let Ok(None) = r.find(&m) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+
+        // ranges are only valid on numeric columns of the same type
+        let m = vec![Match::Any, Match::Any, Match::Gt(Value::Str("a"))];
+        if let Err(oxide::Error::WrongMatchType(i)) = r.find(&m) {
+            assert_eq!(2, i);
+        } else {
+            assert!(false);
+        }
+        let m = vec![Match::Gt(Value::UInt(1)), Match::Any, Match::Any];
+        if let Err(oxide::Error::WrongMatchType(i)) = r.find(&m) {
+            assert_eq!(0, i);
+        } else {
+            assert!(false);
+        }
+
+        use oxide::Pattern;
+        let c1 = r.get_column_ref(0).unwrap();
+        let c2 = r.get_column_ref(1).unwrap();
+        let c3 = r.get_column_ref(2).unwrap();
+        let a = Value::Str("a");
+
+        let p = Pattern::le(&c1, 0isize) & Pattern::range(&c2, 20usize, 40usize);
+        if let Ok(Some(res)) = r.find_pattern(&p) {
+            assert_eq!(2, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let p = Pattern::lt(&c2, 20usize) | (Pattern::ge(&c1, 20isize) & Pattern::new(&c3, &a));
+        if let Ok(Some(res)) = r.find_pattern(&p) {
+            assert_eq!(2, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let p = Pattern::gt(&c3, "a");
+        if let Err(oxide::Error::InvalidColumnMatch) = r.find_pattern(&p) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+    });
+}
+
+#[test]
+fn find_fixed_width_ints() {
+    let n = "foo";
+    let mut c = oxide::Cache::new();
+    {
+        let mut bb = oxide::BucketBuilder::new(n);
+        bb = bb.add_column(oxide::ColumnBuilder::Int.ordered());
+        bb = bb.add_column(oxide::ColumnBuilder::UInt.ordered());
+        c.new_bucket(bb).unwrap();
+    }
+
+    c.bucket_mut(n, |w| {
+        let mut w = w.unwrap();
+        w.insert(vals![std::i64::MIN, 0u64]).unwrap();
+        w.insert(vals![-1i64, 1u64]).unwrap();
+        w.insert(vals![0i64, std::u64::MAX]).unwrap();
+        w.insert(vals![std::i64::MAX, 1usize]).unwrap();
+    });
+
+    c.bucket(n, |r| {
+        let r = r.unwrap();
+
+        use oxide::{Match, Value};
+        let m = matches![std::i64::MIN, Match::Any];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(1, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let m = matches![Match::Any, std::u64::MAX];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(1, res.len());
+        } else {
+            assert!(false);
+        }
+
+        // negatives keep their signed order in the index
+        let m = vec![Match::Lt(Value::Int(0)), Match::Any];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(2, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let m = vec![Match::Range(Value::Int(-1), Value::Int(std::i64::MAX)),
+                     Match::Le(Value::UInt(1))];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(2, res.len());
+        } else {
+            assert!(false);
+        }
+    });
+}
+
+#[test]
+fn find_float() {
+    let n = "foo";
+    let mut c = oxide::Cache::new();
+    {
+        let mut bb = oxide::BucketBuilder::new(n);
+        bb = bb.add_column(oxide::ColumnBuilder::Float.ordered());
+        bb = bb.add_column(oxide::ColumnBuilder::Float);
+        c.new_bucket(bb).unwrap();
+    }
+
+    c.bucket_mut(n, |w| {
+        let mut w = w.unwrap();
+        w.insert(vals![-1.5f64, 0.0f64]).unwrap();
+        w.insert(vals![0.0f64, -0.0f64]).unwrap();
+        w.insert(vals![-0.0f64, 1.0f64]).unwrap();
+        w.insert(vals![std::f64::NAN, std::f64::INFINITY]).unwrap();
+        w.insert(vals![std::f64::INFINITY, std::f64::NAN]).unwrap();
+        w.insert(vals![2.5f64, -std::f64::NAN]).unwrap();
+
+        let stats = w.stats();
+        assert_eq!(5, stats.index_stats[0].cardinality);
+        assert_eq!(4, stats.index_stats[1].cardinality);
+    });
+
+    c.bucket(n, |r| {
+        let r = r.unwrap();
+
+        use oxide::{Match, Value};
+        // -0.0 and 0.0 are the same key
+        let m = matches![0.0f64, Match::Any];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(2, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let m = matches![Match::Any, -0.0f64];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(2, res.len());
+        } else {
+            assert!(false);
+        }
+
+        // every NaN is equal to every other NaN
+        let m = matches![Match::Any, std::f64::NAN];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(2, res.len());
+        } else {
+            assert!(false);
+        }
+
+        // but never part of a range
+        let m = vec![Match::Gt(Value::Float(0.0)), Match::Any];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(2, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let m = vec![Match::Any, Match::Ge(Value::Float(-1.0))];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(4, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let m = vec![Match::Range(Value::Float(-2.0), Value::Float(std::f64::NAN)), Match::Any];
+        if let Ok(None) = r.find(&m) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+
+        let m = vec![Match::Lt(Value::Float(0.0)), Match::Any];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(1, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let m = vec![Match::Lt(Value::UInt(0)), Match::Any];
+        if let Err(oxide::Error::WrongMatchType(i)) = r.find(&m) {
+            assert_eq!(0, i);
+        } else {
+            assert!(false);
+        }
+
+        use oxide::Pattern;
+        let c1 = r.get_column_ref(0).unwrap();
+        let p = Pattern::range(&c1, -1.5f64, 1.0f64);
+        if let Ok(Some(res)) = r.find_pattern(&p) {
+            assert_eq!(3, res.len());
+        } else {
+            assert!(false);
+        }
+    });
+}
+
+#[test]
+fn find_null() {
+    let n = "foo";
+    let mut c = oxide::Cache::new();
+    {
+        let mut bb = oxide::BucketBuilder::new(n);
+        bb = bb.add_column(oxide::ColumnBuilder::UInt);
+        bb = bb.add_column(oxide::ColumnBuilder::Str.nullable());
+        bb = bb.add_column(oxide::ColumnBuilder::Int.ordered().nullable());
+        c.new_bucket(bb).unwrap();
+    }
+
+    c.bucket_mut(n, |w| {
+        let mut w = w.unwrap();
+        use oxide::Value;
+        w.insert(vals![1usize, "a", 10isize]).unwrap();
+        w.insert(vec![Value::UInt(2), Value::Null, Value::Int(20)]).unwrap();
+        w.insert(vals![3usize, Some("b"), None::<isize>]).unwrap();
+        w.insert(vec![Value::UInt(4), Value::Null, Value::Null]).unwrap();
+
+        if let Err(oxide::Error::NotNullable(i)) = w.insert(vec![Value::Null,
+                                                                 Value::Str("a"),
+                                                                 Value::Int(1)]) {
+            assert_eq!(0, i);
+        } else {
+            assert!(false);
+        }
+
+        // a second identical row with nulls is still a duplicate
+        assert_eq!(false,
+                   w.insert_unique(vec![Value::UInt(4), Value::Null, Value::Null]).unwrap());
+
+        let stats = w.stats();
+        assert_eq!(4, stats.rows);
+        assert_eq!(0, stats.index_stats[0].nulls);
+        assert_eq!(2, stats.index_stats[1].nulls);
+        assert_eq!(2, stats.index_stats[1].cardinality);
+        assert_eq!(2, stats.index_stats[2].nulls);
+    });
+
+    c.bucket(n, |r| {
+        let r = r.unwrap();
+
+        use oxide::{Match, Value};
+        let m = vec![Match::Any, Match::Null, Match::Any];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(2, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let m = vec![Match::Any, Match::NotNull, Match::Null];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(1, res.len());
+            assert_eq!("null", format!("{}", res.iter().next().unwrap()[2]));
+        } else {
+            assert!(false);
+        }
+
+        // ranges skip nulls
+        let m = vec![Match::Any, Match::Any, Match::Ge(Value::Int(0))];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(2, res.len());
+        } else {
+            assert!(false);
+        }
+
+        // null on a non nullable column is a valid match that finds nothing
+        let m = vec![Match::Null, Match::Any, Match::Any];
+        if let Ok(None) = r.find(&m) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+
+        use oxide::Pattern;
+        let c2 = r.get_column_ref(1).unwrap();
+        let c3 = r.get_column_ref(2).unwrap();
+        let p = Pattern::null(&c2) | Pattern::null(&c3);
+        if let Ok(Some(res)) = r.find_pattern(&p) {
+            assert_eq!(3, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let p = Pattern::not_null(&c2) & Pattern::not_null(&c3);
+        if let Ok(Some(res)) = r.find_pattern(&p) {
+            assert_eq!(1, res.len());
+        } else {
+            assert!(false);
+        }
+
+        // equality against null is not a thing, use Pattern::null
+        let null = Value::Null;
+        let p = Pattern::new(&c2, &null);
+        if let Err(oxide::Error::InvalidColumnMatch) = r.find_pattern(&p) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+    });
+}
+
+#[test]
+fn find_not() {
+    let n = "foo";
+    let mut c = oxide::Cache::new();
+    {
+        let mut bb = oxide::BucketBuilder::new(n);
+        bb = bb.add_column(oxide::ColumnBuilder::Str);
+        bb = bb.add_column(oxide::ColumnBuilder::Str);
+        c.new_bucket(bb).unwrap();
+    }
+
+    c.bucket_mut(n, |w| {
+        let mut w = w.unwrap();
+        w.insert(vals!["active", "eu"]).unwrap();
+        w.insert(vals!["archived", "eu"]).unwrap();
+        w.insert(vals!["active", "us"]).unwrap();
+        w.insert(vals!["pending", "eu"]).unwrap();
+        w.insert(vals!["pending", "us"]).unwrap();
+
+        // deleted rows never come back through a negation
+        assert_eq!(1, w.delete(&matches!["pending", "us"]).unwrap());
+    });
+
+    c.bucket(n, |r| {
+        let r = r.unwrap();
+
+        use oxide::{Match, Value};
+        let m = vec![Match::Not(Value::Str("archived")), Match::Str("eu")];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(2, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let m = vec![Match::Not(Value::Str("nope")), Match::Any];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(4, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let m = vec![Match::Not(Value::UInt(1)), Match::Any];
+        if let Err(oxide::Error::WrongMatchType(i)) = r.find(&m) {
+            assert_eq!(0, i);
+        } else {
+            assert!(false);
+        }
+
+        use oxide::Pattern;
+        let c1 = r.get_column_ref(0).unwrap();
+        let c2 = r.get_column_ref(1).unwrap();
+        let archived = Value::Str("archived");
+        let eu = Value::Str("eu");
+
+        let p = !Pattern::new(&c1, &archived) & Pattern::new(&c2, &eu);
+        if let Ok(Some(res)) = r.find_pattern(&p) {
+            assert_eq!(2, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let p = Pattern::new(&c2, &eu).not();
+        if let Ok(Some(res)) = r.find_pattern(&p) {
+            assert_eq!(1, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let p = Pattern::ne(&c1, "archived") & !Pattern::ne(&c2, "eu");
+        if let Ok(Some(res)) = r.find_pattern(&p) {
+            assert_eq!(2, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let p = !(Pattern::new(&c2, &eu) | !Pattern::new(&c2, &eu));
+        if let Ok(None) = r.find_pattern(&p) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+    });
+
+    // null rows are not unequal to a value
+    let m = "bar";
+    {
+        let mut bb = oxide::BucketBuilder::new(m);
+        bb = bb.add_column(oxide::ColumnBuilder::UInt);
+        bb = bb.add_column(oxide::ColumnBuilder::Int.nullable());
+        c.new_bucket(bb).unwrap();
+    }
+    c.bucket_mut(m, |w| {
+        use oxide::{Match, Value};
+        let mut w = w.unwrap();
+        w.insert(vals![1usize, 1isize]).unwrap();
+        w.insert(vals![2usize, 2isize]).unwrap();
+        w.insert(vals![3usize, Value::Null]).unwrap();
+        if let Ok(Some(res)) = w.find(&vec![Match::Any, Match::Not(Value::Int(1))]) {
+            assert_eq!(1, res.len());
+            assert_eq!("2", format!("{}", res.iter().next().unwrap()[0]));
+        } else {
+            assert!(false);
+        }
+
+        // a negated pattern on the column leaves the null row out the same way
+        use oxide::Pattern;
+        let c2 = w.get_column_ref(1).unwrap();
+        let one = Value::Int(1);
+        let ids = |p: &Pattern| -> Vec<String> {
+            match w.find_pattern(p).unwrap() {
+                Some(res) => res.iter().map(|row| format!("{}", row[0])).collect(),
+                None => Vec::new(),
+            }
+        };
+        assert_eq!(vec!["2"], ids(&Pattern::ne(&c2, 1isize)));
+        assert_eq!(vec!["2"], ids(&!Pattern::new(&c2, &one)));
+        assert_eq!(vec!["1"], ids(&!Pattern::ge(&c2, 2isize)));
+        assert_eq!(vec!["1", "2"], ids(&!Pattern::null(&c2)));
+        assert_eq!(vec!["3"], ids(&!Pattern::not_null(&c2)));
+        // over an Or every row the inner pattern leaves out is taken, the null one too
+        assert_eq!(vec!["3"], ids(&!(Pattern::new(&c2, &one) | Pattern::ge(&c2, 2isize))));
+    });
+}
+
+#[test]
+fn find_in() {
+    let n = "foo";
+    let mut c = oxide::Cache::new();
+    {
+        let mut bb = oxide::BucketBuilder::new(n);
+        bb = bb.add_column(oxide::ColumnBuilder::UInt);
+        bb = bb.add_column(oxide::ColumnBuilder::OwnedStr.nullable());
+        c.new_bucket(bb).unwrap();
+    }
+
+    c.bucket_mut(n, |w| {
+        let mut w = w.unwrap();
+        for i in 0..100usize {
+            let tag = if i % 10 == 0 {
+                None
+            } else {
+                Some(format!("t{}", i % 3))
+            };
+            w.insert(vals![i, tag]).unwrap();
+        }
+    });
+
+    c.bucket(n, |r| {
+        let r = r.unwrap();
+
+        use oxide::{Match, Value};
+        let ids: Vec<Value> = (0..200u64).filter(|i| i % 2 == 1).map(|i| Value::UInt(i)).collect();
+        let m = vec![Match::In(ids.clone()), Match::Any];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(50, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let m = vec![Match::In(ids.clone()), Match::In(vec![Value::OwnedStr("t1".to_owned()), Value::Null])];
+        if let Ok(Some(res)) = r.find(&m) {
+            // odd i with i % 3 == 1, plus odd multiples of 10 which there are none of
+            assert_eq!(17, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let m = vec![Match::In(vec![]), Match::Any];
+        if let Ok(None) = r.find(&m) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+
+        let m = vec![Match::In(vec![Value::UInt(1), Value::Int(2)]), Match::Any];
+        if let Err(oxide::Error::WrongMatchType(i)) = r.find(&m) {
+            assert_eq!(0, i);
+        } else {
+            assert!(false);
+        }
+
+        use oxide::Pattern;
+        let c1 = r.get_column_ref(0).unwrap();
+        let c2 = r.get_column_ref(1).unwrap();
+        let p = Pattern::any_of(&c1, vec![Value::UInt(10), Value::UInt(11), Value::UInt(12)]) &
+                Pattern::not_null(&c2);
+        if let Ok(Some(res)) = r.find_pattern(&p) {
+            assert_eq!(2, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let p = Pattern::any_of(&c2, vec![Value::OwnedStr("t0".to_owned())]);
+        if let Ok(Some(res)) = r.find_pattern(&p) {
+            assert_eq!(30, res.len());
+        } else {
+            assert!(false);
+        }
+    });
+}
+
+#[test]
+fn find_prefix() {
+    let n = "foo";
+    let mut c = oxide::Cache::new();
+    {
+        let mut bb = oxide::BucketBuilder::new(n);
+        bb = bb.add_column(oxide::ColumnBuilder::Str.ordered());
+        bb = bb.add_column(oxide::ColumnBuilder::OwnedStr.case_insensitive());
+        bb = bb.add_column(oxide::ColumnBuilder::UInt);
+        c.new_bucket(bb).unwrap();
+    }
+
+    c.bucket_mut(n, |w| {
+        let mut w = w.unwrap();
+        // only the case insensitive column keeps lowercased keys, the other walks its keys
+        let stats = w.stats();
+        assert!(!stats.index_stats[0].case_folded);
+        assert!(stats.index_stats[1].case_folded);
+        assert!(!stats.index_stats[2].case_folded);
+        w.insert(vals!["alice", "Alice".to_owned(), 1usize]).unwrap();
+        w.insert(vals!["alicia", "ALICE".to_owned(), 2usize]).unwrap();
+        w.insert(vals!["al", "bob".to_owned(), 3usize]).unwrap();
+        w.insert(vals!["bob", "Bobby".to_owned(), 4usize]).unwrap();
+        w.insert(vals!["alb", "alice".to_owned(), 5usize]).unwrap();
+    });
+
+    c.bucket(n, |r| {
+        let r = r.unwrap();
+
+        use oxide::Match;
+        let m = vec![Match::Prefix("ali"), Match::Any, Match::Any];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(2, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let m = vec![Match::Prefix("al"), Match::Any, Match::Any];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(4, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let m = vec![Match::Prefix(""), Match::Any, Match::Any];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(5, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let m = vec![Match::Prefix("z"), Match::Any, Match::Any];
+        if let Ok(None) = r.find(&m) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+
+        // hashed index, prefix is case sensitive
+        let m = vec![Match::Any, Match::Prefix("Bob"), Match::Any];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(1, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let m = vec![Match::Any, Match::IgnoreCase("aLiCe"), Match::Any];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(3, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let m = vec![Match::IgnoreCase("BOB"), Match::IgnoreCase("bobby"), Match::Any];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(1, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let m = vec![Match::Any, Match::Any, Match::Prefix("1")];
+        if let Err(oxide::Error::WrongMatchType(i)) = r.find(&m) {
+            assert_eq!(2, i);
+        } else {
+            assert!(false);
+        }
+
+        use oxide::Pattern;
+        let c1 = r.get_column_ref(0).unwrap();
+        let c2 = r.get_column_ref(1).unwrap();
+        let p = Pattern::prefix(&c1, "ali") & Pattern::ignore_case(&c2, "alice");
+        if let Ok(Some(res)) = r.find_pattern(&p) {
+            assert_eq!(2, res.len());
+        } else {
+            assert!(false);
+        }
+    });
+
+    // case folded keys follow deletes and compaction
+    c.bucket_mut(n, |w| {
+        use oxide::Match;
+        let mut w = w.unwrap();
+        assert_eq!(1, w.delete(&vec![Match::Any, Match::Any, Match::UInt(2)]).unwrap());
+        w.compact().unwrap();
+        let m = vec![Match::Any, Match::IgnoreCase("alice"), Match::Any];
+        if let Ok(Some(res)) = w.find(&m) {
+            assert_eq!(2, res.len());
+        } else {
+            assert!(false);
+        }
+    });
+}
+
+#[test]
+fn find_text() {
+    let n = "foo";
+    let mut c = oxide::Cache::new();
+    {
+        let mut bb = oxide::BucketBuilder::new(n);
+        bb = bb.add_column(oxide::ColumnBuilder::UInt);
+        bb = bb.add_column(oxide::ColumnBuilder::Text.stop_words(&["the", "a", "and"]));
+        c.new_bucket(bb).unwrap();
+    }
+
+    c.bucket_mut(n, |w| {
+        let mut w = w.unwrap();
+        w.insert(vals![1usize, "The quick brown fox"]).unwrap();
+        w.insert(vals![2usize, "A lazy dog, and a quick cat!".to_owned()]).unwrap();
+        w.insert(vals![3usize, "brown-dog"]).unwrap();
+
+        // duplicates are judged on the whole text, not on the words
+        assert_eq!(false, w.insert_unique(vals![3usize, "brown-dog"]).unwrap());
+        assert_eq!(true, w.insert_unique(vals![3usize, "dog brown"]).unwrap());
+        assert_eq!(true, w.insert_unique(vals![4usize, "brown-dog"]).unwrap());
+
+        let stats = w.stats();
+        assert_eq!(5, stats.rows);
+        // quick brown fox lazy dog cat
+        assert_eq!(6, stats.index_stats[1].cardinality);
+    });
+
+    c.bucket(n, |r| {
+        let r = r.unwrap();
+
+        use oxide::Match;
+        let m = vec![Match::Any, Match::ContainsWord("QUICK")];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(2, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let m = vec![Match::Any, Match::ContainsWord("Quick,")];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(2, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let m = vec![Match::Any, Match::ContainsWord("brown-dog")];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(3, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let m = vec![Match::Any, Match::ContainsWord("the")];
+        if let Ok(None) = r.find(&m) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+
+        let m = vec![Match::Any, Match::ContainsAllWords("dog, brown")];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(3, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let m = vec![Match::UInt(3), Match::ContainsAllWords("the brown dog")];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(2, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let m = vec![Match::Any, Match::ContainsAllWords("brown unicorn")];
+        if let Ok(None) = r.find(&m) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+
+        let m = vec![Match::Any, Match::Str("brown-dog")];
+        if let Err(oxide::Error::WrongMatchType(i)) = r.find(&m) {
+            assert_eq!(1, i);
+        } else {
+            assert!(false);
+        }
+
+        use oxide::Pattern;
+        let c2 = r.get_column_ref(1).unwrap();
+        let p = Pattern::contains_word(&c2, "fox") | Pattern::contains_all_words(&c2, "lazy cat");
+        if let Ok(Some(res)) = r.find_pattern(&p) {
+            assert_eq!(2, res.len());
+        } else {
+            assert!(false);
+        }
+    });
+}
+
+#[test]
+fn find_tags() {
+    let n = "foo";
+    let mut c = oxide::Cache::new();
+    {
+        let mut bb = oxide::BucketBuilder::new(n);
+        bb = bb.add_column(oxide::ColumnBuilder::UInt);
+        bb = bb.add_column(oxide::ColumnBuilder::Tags);
+        c.new_bucket(bb).unwrap();
+    }
+
+    c.bucket_mut(n, |w| {
+        let mut w = w.unwrap();
+        use oxide::Value;
+        w.insert(vals![1usize, vec!["red", "blue"]]).unwrap();
+        w.insert(vals![2usize, vec!["red".to_owned(), "red".to_owned()]]).unwrap();
+        w.insert(vals![3usize, vec![Value::Str("green"), Value::UInt(7)]]).unwrap();
+        w.insert(vals![4usize, Vec::<Value>::new()]).unwrap();
+
+        if let Err(oxide::Error::WrongValueType(i)) = w.insert(vals![5usize, vec![true]]) {
+            assert_eq!(1, i);
+        } else {
+            assert!(false);
+        }
+
+        // tags are a set, order and repeats do not make a new row
+        assert_eq!(false, w.insert_unique(vals![1usize, vec!["blue", "red", "blue"]]).unwrap());
+        assert_eq!(false, w.insert_unique(vals![2usize, vec!["red".to_owned()]]).unwrap());
+        assert_eq!(false, w.insert_unique(vals![4usize, Vec::<Value>::new()]).unwrap());
+        assert_eq!(true, w.insert_unique(vals![1usize, vec!["red"]]).unwrap());
+        assert_eq!(true, w.insert_unique(vals![1usize, vec!["red", "blue", "green"]]).unwrap());
+
+        let stats = w.stats();
+        assert_eq!(6, stats.rows);
+        assert_eq!(4, stats.index_stats[1].cardinality);
+    });
+
+    c.bucket(n, |r| {
+        let r = r.unwrap();
+
+        use oxide::{Match, Value};
+        let m = vec![Match::Any, Match::HasAny(vec![Value::Str("blue"), Value::UInt(7)])];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(3, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let m = vec![Match::Any, Match::HasAll(vec![Value::OwnedStr("red".to_owned()), Value::Str("blue")])];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(2, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let m = vec![Match::Any, Match::HasAll(vec![Value::Str("red"), Value::Str("pink")])];
+        if let Ok(None) = r.find(&m) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+
+        // every row has all of no tags
+        let m = vec![Match::Any, Match::HasAll(vec![])];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(6, res.len());
+        } else {
+            assert!(false);
+        }
+
+        // 7 the number is not "7" the string
+        let m = vec![Match::Any, Match::HasAny(vec![Value::Str("7")])];
+        if let Ok(None) = r.find(&m) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+
+        let m = vec![Match::Any, Match::Str("red")];
+        if let Err(oxide::Error::WrongMatchType(i)) = r.find(&m) {
+            assert_eq!(1, i);
+        } else {
+            assert!(false);
+        }
+
+        let m = vec![Match::UInt(2), Match::Any];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!("[red]", format!("{}", res.iter().next().unwrap()[1]));
+        } else {
+            assert!(false);
+        }
+
+        use oxide::Pattern;
+        let c1 = r.get_column_ref(0).unwrap();
+        let c2 = r.get_column_ref(1).unwrap();
+        let p = Pattern::has_all(&c2, vec![Value::Str("red")]) & !Pattern::has_any(&c2, vec![Value::Str("blue")]) &
+                Pattern::lt(&c1, 3usize);
+        if let Ok(Some(res)) = r.find_pattern(&p) {
+            assert_eq!(2, res.len());
+        } else {
+            assert!(false);
+        }
+
+        let p = Pattern::has_all(&c2, vec![]) & Pattern::lt(&c1, 3usize);
+        if let Ok(Some(res)) = r.find_pattern(&p) {
+            assert_eq!(4, res.len());
+        } else {
+            assert!(false);
+        }
+    });
+}
+
+#[test]
+fn compact() {
+    let n = "foo";
+    let mut c = oxide::Cache::new();
+    {
+        let mut bb = oxide::BucketBuilder::new(n);
+        bb = bb.add_column(oxide::ColumnBuilder::UInt.ordered());
+        bb = bb.add_column(oxide::ColumnBuilder::Str.nullable());
+        c.new_bucket(bb).unwrap();
+    }
+
+    c.bucket_mut(n, |w| {
+        let mut w = w.unwrap();
+        use oxide::{Match, Value};
+        for i in 0..10usize {
+            let s = if i % 2 == 0 { Value::Str("even") } else { Value::Null };
+            w.insert(vec![Value::UInt(i as u64), s]).unwrap();
+        }
+        assert_eq!(0, w.compact().unwrap());
+
+        assert_eq!(5, w.delete(&vec![Match::Any, Match::Null]).unwrap());
+        assert_eq!(1, w.delete(&matches![4usize, "even"]).unwrap());
+        assert_eq!(4, w.rows());
+
+        assert_eq!(6, w.compact().unwrap());
+        assert_eq!(4, w.rows());
+        let stats = w.stats();
+        assert_eq!(10, stats.inserts);
+        assert_eq!(6, stats.deletes);
+        assert_eq!(4, stats.rows);
+        assert_eq!(1, stats.compactions);
+        assert_eq!(6, stats.reclaimed);
+        assert_eq!(4, stats.index_stats[0].cardinality);
+        assert_eq!(1, stats.index_stats[1].cardinality);
+        assert_eq!(0, stats.index_stats[1].nulls);
+
+        // surviving rows are still found through their renumbered ids
+        let m = vec![Match::Ge(Value::UInt(6)), Match::Str("even")];
+        if let Ok(Some(res)) = w.find(&m) {
+            assert_eq!(2, res.len());
+            let found: Vec<String> = res.iter().map(|r| format!("{}", r[0])).collect();
+            assert_eq!(vec!["6".to_owned(), "8".to_owned()], found);
+        } else {
+            assert!(false);
+        }
+
+        w.insert(vals![100usize, "even"]).unwrap();
+        assert_eq!(5, w.rows());
+        let m = vec![Match::Any, Match::Str("even")];
+        if let Ok(Some(res)) = w.find(&m) {
+            assert_eq!(5, res.len());
+        } else {
+            assert!(false);
+        }
+    });
+}
+
+#[test]
+fn auto_compact() {
+    let n = "foo";
+    let mut c = oxide::Cache::new();
+    {
+        let mut bb = oxide::BucketBuilder::new(n).auto_compact(0.5);
+        bb = bb.add_column(oxide::ColumnBuilder::UInt);
+        c.new_bucket(bb).unwrap();
+    }
+
+    c.bucket_mut(n, |w| {
+        let mut w = w.unwrap();
+        for i in 0..4usize {
+            w.insert(vals![i]).unwrap();
+        }
+        w.delete(&matches![0usize]).unwrap();
+        assert_eq!(0, w.stats().compactions);
+        w.delete(&matches![1usize]).unwrap();
+        let stats = w.stats();
+        assert_eq!(1, stats.compactions);
+        assert_eq!(2, stats.reclaimed);
+        assert_eq!(2, stats.rows);
+        assert_eq!(2, stats.index_stats[0].cardinality);
+        assert_eq!(1, w.find(&matches![3usize]).unwrap().unwrap().len());
+    });
+}
+
+#[test]
+fn update() {
+    let n = "foo";
+    let mut c = oxide::Cache::new();
+    {
+        let mut bb = oxide::BucketBuilder::new(n);
+        bb = bb.add_column(oxide::ColumnBuilder::UInt);
+        bb = bb.add_column(oxide::ColumnBuilder::Str.nullable());
+        bb = bb.add_column(oxide::ColumnBuilder::Int.ordered());
+        c.new_bucket(bb).unwrap();
+    }
+
+    c.bucket_mut(n, |w| {
+        let mut w = w.unwrap();
+        use oxide::{Match, Value};
+        w.insert(vals![1usize, "new", 10isize]).unwrap();
+        w.insert(vals![2usize, "new", 20isize]).unwrap();
+        w.insert(vals![3usize, "done", 30isize]).unwrap();
+
+        if let Err(oxide::Error::WrongValueType(i)) =
+               w.update(&vec![Match::Any, Match::Str("new"), Match::Any],
+                        vec![(2, Value::UInt(1))]) {
+            assert_eq!(2, i);
+        } else {
+            assert!(false);
+        }
+        if let Err(oxide::Error::NotNullable(i)) =
+               w.update(&vec![Match::Any, Match::Str("new"), Match::Any],
+                        vec![(0, Value::Null)]) {
+            assert_eq!(0, i);
+        } else {
+            assert!(false);
+        }
+        if let Err(oxide::Error::NoSuchColumn(i)) =
+               w.update(&vec![Match::Any, Match::Str("new"), Match::Any],
+                        vec![(3, Value::Null)]) {
+            assert_eq!(3, i);
+        } else {
+            assert!(false);
+        }
+
+        let n = w.update(&vec![Match::Any, Match::Str("new"), Match::Any],
+                         vec![(1, Value::Str("done")), (2, Value::Int(-1))])
+                 .unwrap();
+        assert_eq!(2, n);
+        let n = w.update(&vec![Match::Any, Match::Str("nope"), Match::Any],
+                         vec![(1, Value::Null)])
+                 .unwrap();
+        assert_eq!(0, n);
+
+        let stats = w.stats();
+        assert_eq!(3, stats.inserts);
+        assert_eq!(3, stats.rows);
+        // "new" and 10, 20 left the indices with their last rows
+        assert_eq!(1, stats.index_stats[1].cardinality);
+        assert_eq!(2, stats.index_stats[2].cardinality);
+
+        if let Ok(None) = w.find(&vec![Match::Any, Match::Str("new"), Match::Any]) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+        let m = vec![Match::Any, Match::Str("done"), Match::Lt(Value::Int(0))];
+        if let Ok(Some(res)) = w.find(&m) {
+            assert_eq!(2, res.len());
+            assert_eq!("-1", format!("{}", res.iter().next().unwrap()[2]));
+        } else {
+            assert!(false);
+        }
+
+        use oxide::Pattern;
+        let c1 = w.get_column_ref(0).unwrap();
+        let n = {
+            let p = Pattern::ge(&c1, 2usize);
+            w.update_pattern(&p, vec![(1, Value::Null)]).unwrap()
+        };
+        assert_eq!(2, n);
+        let m = vec![Match::Any, Match::Null, Match::Any];
+        if let Ok(Some(res)) = w.find(&m) {
+            assert_eq!(2, res.len());
+        } else {
+            assert!(false);
+        }
+        assert_eq!(2, w.stats().index_stats[1].nulls);
+    });
+}
+
+#[test]
+fn key() {
+    let n = "foo";
+    let mut c = oxide::Cache::new();
+    {
+        let mut bb = oxide::BucketBuilder::new(n);
+        bb = bb.add_column(oxide::ColumnBuilder::Tags.key());
+        if let Err(oxide::Error::InvalidKeyColumn(i)) = c.new_bucket(bb) {
+            assert_eq!(0, i);
+        } else {
+            assert!(false);
+        }
+    }
+    {
+        let mut bb = oxide::BucketBuilder::new(n);
+        bb = bb.add_column(oxide::ColumnBuilder::UInt.key());
+        bb = bb.add_column(oxide::ColumnBuilder::Str.key());
+        bb = bb.add_column(oxide::ColumnBuilder::Int);
+        c.new_bucket(bb).unwrap();
+    }
+
+    c.bucket_mut(n, |w| {
+        let mut w = w.unwrap();
+        use oxide::{Match, Value};
+        w.insert(vals![1usize, "a", 10isize]).unwrap();
+        w.insert(vals![1usize, "b", 20isize]).unwrap();
+        if let Err(oxide::Error::DuplicateKey(k)) = w.insert(vals![1usize, "a", 30isize]) {
+            assert_eq!("1, a", k);
+        } else {
+            assert!(false);
+        }
+        assert_eq!(false, w.insert_unique(vals![1usize, "a", 10isize]).unwrap());
+        assert_eq!(2, w.stats().inserts);
+
+        if let Ok(Some(row)) = w.get_by_key(&vals![1usize, "b"]) {
+            assert_eq!("20", format!("{}", row[2]));
+        } else {
+            assert!(false);
+        }
+        if let Ok(None) = w.get_by_key(&vals![2usize, "b"]) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+        if let Err(oxide::Error::WrongNumberOfValues(2, 1)) = w.get_by_key(&vals![1usize]) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+
+        // replaced in place, no new id is used
+        assert_eq!(false, w.upsert(vals![1usize, "a", 11isize]).unwrap());
+        assert_eq!(true, w.upsert(vals![2usize, "a", 12isize]).unwrap());
+        assert_eq!(3, w.stats().inserts);
+        assert_eq!(3, w.rows());
+        if let Ok(Some(row)) = w.get_by_key(&vals![1usize, "a"]) {
+            assert_eq!("11", format!("{}", row[2]));
+        } else {
+            assert!(false);
+        }
+        if let Ok(None) = w.find(&vec![Match::Any, Match::Any, Match::Int(10)]) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+
+        // moving a key onto another live row fails, a deleted row frees its key
+        if let Err(oxide::Error::DuplicateKey(_)) =
+               w.update(&vec![Match::UInt(2), Match::Any, Match::Any],
+                        vec![(0, Value::UInt(1))]) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+        assert_eq!(1, w.delete(&vec![Match::UInt(1), Match::Str("a"), Match::Any]).unwrap());
+        assert_eq!(1,
+                   w.update(&vec![Match::UInt(2), Match::Any, Match::Any],
+                            vec![(0, Value::UInt(1))])
+                    .unwrap());
+        if let Ok(Some(row)) = w.get_by_key(&vals![1usize, "a"]) {
+            assert_eq!("12", format!("{}", row[2]));
+        } else {
+            assert!(false);
+        }
+        if let Ok(None) = w.get_by_key(&vals![2usize, "a"]) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+
+        w.compact().unwrap();
+        if let Ok(Some(row)) = w.get_by_key(&vals![1usize, "b"]) {
+            assert_eq!("20", format!("{}", row[2]));
+        } else {
+            assert!(false);
+        }
+        w.insert(vals![2usize, "a", 30isize]).unwrap();
+        if let Ok(Some(row)) = w.get_by_key(&vals![2usize, "a"]) {
+            assert_eq!("30", format!("{}", row[2]));
+        } else {
+            assert!(false);
+        }
+    });
+
+    let m = "bar";
+    c.new_bucket(oxide::BucketBuilder::new(m).add_column(oxide::ColumnBuilder::UInt)).unwrap();
+    c.bucket_mut(m, |w| {
+        let mut w = w.unwrap();
+        if let Err(oxide::Error::NoKey) = w.upsert(vals![1usize]) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+    });
+}
+
+#[test]
+fn unique() {
+    let n = "foo";
+    let mut c = oxide::Cache::new();
+    {
+        let mut bb = oxide::BucketBuilder::new(n);
+        bb = bb.add_column(oxide::ColumnBuilder::UInt.key());
+        bb = bb.add_column(oxide::ColumnBuilder::OwnedStr.unique().nullable());
+        c.new_bucket(bb).unwrap();
+    }
+
+    c.bucket_mut(n, |w| {
+        let mut w = w.unwrap();
+        use oxide::{Match, Value};
+        w.insert(vals![1usize, "a@x".to_owned()]).unwrap();
+        w.insert(vals![2usize, "b@x".to_owned()]).unwrap();
+        w.insert(vals![3usize, Value::Null]).unwrap();
+        w.insert(vals![4usize, Value::Null]).unwrap();
+        if let Err(oxide::Error::UniqueViolation { column, value }) =
+               w.insert(vals![5usize, "a@x".to_owned()]) {
+            assert_eq!(1, column);
+            assert_eq!("a@x", value);
+        } else {
+            assert!(false);
+        }
+        if let Err(oxide::Error::UniqueViolation { .. }) =
+               w.upsert(vals![2usize, "a@x".to_owned()]) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+        assert_eq!(false, w.upsert(vals![2usize, "b@x".to_owned()]).unwrap());
+        if let Err(oxide::Error::UniqueViolation { .. }) =
+               w.update(&vec![Match::Any, Match::Null],
+                        vec![(1, Value::OwnedStr("c@x".to_owned()))]) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+        assert_eq!(1,
+                   w.update(&vec![Match::UInt(3), Match::Any],
+                            vec![(1, Value::OwnedStr("c@x".to_owned()))])
+                    .unwrap());
+        assert_eq!(4, w.stats().inserts);
+
+        // a deleted row gives its value up
+        assert_eq!(1, w.delete(&vec![Match::UInt(1), Match::Any]).unwrap());
+        w.insert(vals![5usize, "a@x".to_owned()]).unwrap();
+    });
+
+    let m = "bar";
+    let bb = oxide::BucketBuilder::new(m).add_column(oxide::ColumnBuilder::Tags.unique());
+    if let Err(oxide::Error::InvalidUniqueColumn(0)) = c.new_bucket(bb) {
+        assert!(true);
+    } else {
+        assert!(false);
+    }
+}
+
+#[test]
+fn shared_cache() {
+    use std::sync::Arc;
+    use std::thread;
+
+    let n = "foo";
+    let c = Arc::new(oxide::SharedCache::new());
+    {
+        let mut bb = oxide::BucketBuilder::new(n);
+        bb = bb.add_column(oxide::ColumnBuilder::UInt);
+        bb = bb.add_column(oxide::ColumnBuilder::Str);
+        c.new_bucket(bb).unwrap();
+    }
+
+    let mut threads = Vec::new();
+    for t in 0..4usize {
+        let c = c.clone();
+        threads.push(thread::spawn(move || {
+            for i in 0..100usize {
+                c.bucket_mut(n, |w| {
+                    w.unwrap().insert(vals![t * 100 + i, "x"]).unwrap();
+                });
+                c.bucket(n, |r| {
+                    assert!(r.unwrap().rows() > i);
+                });
+            }
+        }));
+    }
+    // buckets come and go while the others are busy
+    let other = {
+        let c = c.clone();
+        thread::spawn(move || {
+            for _ in 0..100 {
+                let bb = oxide::BucketBuilder::new("bar").add_column(oxide::ColumnBuilder::UInt);
+                c.new_bucket(bb).unwrap();
+                c.drop_bucket("bar");
+            }
+        })
+    };
+    for t in threads {
+        t.join().unwrap();
+    }
+    other.join().unwrap();
+
+    assert_eq!(false, c.has_bucket("bar"));
+    c.bucket(n, |r| {
+        let r = r.unwrap();
+        assert_eq!(400, r.rows());
+        if let Ok(Some(res)) = r.find(&vec![oxide::Match::Any, oxide::Match::Str("x")]) {
+            assert_eq!(400, res.len());
+        } else {
+            assert!(false);
+        }
+    });
+    c.bucket_mut("bar", |w| assert!(w.is_none()));
+}
+
+#[test]
+fn snapshot() {
+    use std::sync::{Arc, mpsc};
+    use std::thread;
+    use oxide::Match;
+
+    let n = "foo";
+    let c = Arc::new(oxide::SharedCache::new());
+    {
+        let mut bb = oxide::BucketBuilder::new(n).snapshots();
+        bb = bb.add_column(oxide::ColumnBuilder::UInt);
+        c.new_bucket(bb).unwrap();
+    }
+
+    c.bucket_mut(n, |w| {
+        let mut w = w.unwrap();
+        w.insert(vals![1usize]).unwrap();
+        w.insert(vals![2usize]).unwrap();
+    });
+    c.bucket(n, |r| assert_eq!(0, r.unwrap().rows()));
+    c.publish(n).unwrap();
+    c.bucket(n, |r| assert_eq!(2, r.unwrap().rows()));
+
+    let old = c.snapshot(n).unwrap();
+
+    // a writer in the middle of a batch does not hold up readers
+    let (started, wait_started) = mpsc::channel();
+    let (finish, wait_finish) = mpsc::channel::<()>();
+    let writer = {
+        let c = c.clone();
+        thread::spawn(move || {
+            c.bucket_mut(n, |w| {
+                let mut w = w.unwrap();
+                w.delete(&vec![Match::UInt(1)]).unwrap();
+                started.send(()).unwrap();
+                wait_finish.recv().unwrap();
+                w.insert(vals![3usize]).unwrap();
+            });
+        })
+    };
+    wait_started.recv().unwrap();
+    c.bucket(n, |r| {
+        let r = r.unwrap();
+        assert_eq!(2, r.rows());
+        if let Ok(Some(res)) = r.find(&vec![Match::UInt(1)]) {
+            assert_eq!(1, res.len());
+        } else {
+            assert!(false);
+        }
+    });
+    finish.send(()).unwrap();
+    writer.join().unwrap();
+
+    c.publish(n).unwrap();
+    c.bucket(n, |r| {
+        let r = r.unwrap();
+        assert_eq!(2, r.rows());
+        if let Ok(None) = r.find(&vec![Match::UInt(1)]) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+    });
+    // the old view is untouched
+    assert_eq!(2, old.rows());
+    if let Ok(Some(res)) = old.find(&vec![Match::UInt(1)]) {
+        assert_eq!(1, res.len());
+    } else {
+        assert!(false);
+    }
+    if let Ok(None) = old.find(&vec![Match::UInt(3)]) {
+        assert!(true);
+    } else {
+        assert!(false);
+    }
+
+    // without snapshots writes are visible at once, a held snapshot still does not change
+    let m = "bar";
+    c.new_bucket(oxide::BucketBuilder::new(m).add_column(oxide::ColumnBuilder::UInt)).unwrap();
+    let before = c.snapshot(m).unwrap();
+    c.bucket_mut(m, |w| w.unwrap().insert(vals![1usize]).unwrap());
+    c.bucket(m, |r| assert_eq!(1, r.unwrap().rows()));
+    assert_eq!(0, before.rows());
+    assert!(c.snapshot("nope").is_none());
+    if let Err(oxide::Error::InvalidBucket) = c.publish("nope") {
+        assert!(true);
+    } else {
+        assert!(false);
+    }
+}
+
+#[test]
+fn cache_snapshot() {
+    use oxide::{Match, Value};
+
+    let n = "foo";
+    let mut c = oxide::Cache::new();
+    {
+        let mut bb = oxide::BucketBuilder::new(n);
+        bb = bb.add_column(oxide::ColumnBuilder::UInt.key());
+        bb = bb.add_column(oxide::ColumnBuilder::Int);
+        c.new_bucket(bb).unwrap();
+    }
+    // enough rows to fill two whole value chunks
+    c.bucket_mut(n, |w| {
+        let mut w = w.unwrap();
+        for i in 0..2048 {
+            w.insert(vals![i as usize, i as isize]).unwrap();
+        }
+    });
+    let snap = c.snapshot(n).unwrap();
+    assert!(c.snapshot("nope").is_none());
+
+    c.bucket_mut(n, |w| {
+        let mut w = w.unwrap();
+        w.update(&vec![Match::UInt(5), Match::Any], vec![(1, Value::Int(-5))]).unwrap();
+        // the rolled back row was the first of a new chunk
+        let r: Result<(), oxide::Error> = w.transaction(|tx| {
+            try!(tx.insert(vals![2048usize, 2048isize]));
+            Err(oxide::Error::NoKey)
+        }).unwrap();
+        assert!(r.is_err());
+        w.delete(&vec![Match::Lt(Value::UInt(1000)), Match::Any]).unwrap();
+        assert_eq!(1000, w.compact().unwrap());
+        w.insert(vals![3000usize, 3000isize]).unwrap();
+        assert_eq!(1049, w.rows());
+        if let Ok(Some(row)) = w.get_by_key(&vals![1500usize]) {
+            assert_eq!("1500", format!("{}", row[1]));
+        } else {
+            assert!(false);
+        }
+        if let Ok(Some(row)) = w.get_by_key(&vals![3000usize]) {
+            assert_eq!("3000", format!("{}", row[1]));
+        } else {
+            assert!(false);
+        }
+        if let Ok(None) = w.get_by_key(&vals![2048usize]) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+    });
+
+    // the snapshot still has the bucket as it was
+    assert_eq!(2048, snap.rows());
+    if let Ok(Some(row)) = snap.get_by_key(&vals![5usize]) {
+        assert_eq!("5", format!("{}", row[1]));
+    } else {
+        assert!(false);
+    }
+    if let Ok(None) = snap.get_by_key(&vals![3000usize]) {
+        assert!(true);
+    } else {
+        assert!(false);
+    }
+}
+
+#[test]
+fn transaction() {
+    let n = "foo";
+    let mut c = oxide::Cache::new();
+    {
+        let mut bb = oxide::BucketBuilder::new(n);
+        bb = bb.add_column(oxide::ColumnBuilder::UInt.key());
+        bb = bb.add_column(oxide::ColumnBuilder::Int);
+        c.new_bucket(bb).unwrap();
+    }
+
+    c.bucket_mut(n, |w| {
+        let mut w = w.unwrap();
+        use oxide::{Match, Value};
+        w.insert(vals![1usize, 10isize]).unwrap();
+
+        // the duplicate key fails the batch, nothing before it sticks
+        let r = w.transaction(|tx| {
+            try!(tx.insert(vals![2usize, 20isize]));
+            try!(tx.update(&vec![Match::UInt(1), Match::Any], vec![(1, Value::Int(11))]));
+            try!(tx.delete(&vec![Match::UInt(1), Match::Any]));
+            assert_eq!(1, tx.rows());
+            tx.insert(vals![2usize, 21isize])
+        }).unwrap();
+        if let Err(oxide::Error::DuplicateKey(_)) = r {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+        let stats = w.stats();
+        assert_eq!(1, stats.inserts);
+        assert_eq!(0, stats.deletes);
+        if let Ok(Some(row)) = w.get_by_key(&vals![1usize]) {
+            assert_eq!("10", format!("{}", row[1]));
+        } else {
+            assert!(false);
+        }
+        if let Ok(None) = w.get_by_key(&vals![2usize]) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+
+        let r: Result<usize, oxide::Error> = w.transaction(|tx| {
+            try!(tx.insert(vals![2usize, 20isize]));
+            tx.update(&vec![Match::UInt(1), Match::Any], vec![(1, Value::Int(11))])
+        }).unwrap();
+        assert_eq!(1, r.unwrap());
+        assert_eq!(2, w.stats().inserts);
+        if let Ok(Some(row)) = w.get_by_key(&vals![1usize]) {
+            assert_eq!("11", format!("{}", row[1]));
+        } else {
+            assert!(false);
+        }
+        if let Ok(Some(row)) = w.get_by_key(&vals![2usize]) {
+            assert_eq!("20", format!("{}", row[1]));
+        } else {
+            assert!(false);
+        }
+
+        // rekeyed, deleted and compacted rows all come back, inner transactions included
+        let r: Result<(), oxide::Error> = w.transaction(|tx| {
+            try!(tx.update(&vec![Match::UInt(1), Match::Any], vec![(0, Value::UInt(5))]));
+            try!(tx.delete(&vec![Match::UInt(2), Match::Any]));
+            assert_eq!(1, try!(tx.compact()));
+            try!(try!(tx.transaction(|inner| inner.insert(vals![6usize, 60isize]))));
+            let r: Result<(), oxide::Error> = try!(tx.transaction(|inner| {
+                try!(inner.insert(vals![7usize, 70isize]));
+                inner.insert(vals![7usize, 71isize])
+            }));
+            assert!(r.is_err());
+            assert_eq!(2, tx.rows());
+            if let Ok(None) = tx.get_by_key(&vals![7usize]) {
+                assert!(true);
+            } else {
+                assert!(false);
+            }
+            Err(oxide::Error::NoKey)
+        }).unwrap();
+        assert!(r.is_err());
+        assert_eq!(2, w.rows());
+        let stats = w.stats();
+        assert_eq!(2, stats.inserts);
+        assert_eq!(0, stats.deletes);
+        assert_eq!(0, stats.compactions);
+        for &(k, v) in [(1usize, "11"), (2, "20")].iter() {
+            if let Ok(Some(row)) = w.get_by_key(&vals![k]) {
+                assert_eq!(v, format!("{}", row[1]));
+            } else {
+                assert!(false);
+            }
+        }
+        for &k in [5usize, 6].iter() {
+            if let Ok(None) = w.get_by_key(&vals![k]) {
+                assert!(true);
+            } else {
+                assert!(false);
+            }
+        }
+        if let Ok(Some(res)) = w.find(&vec![Match::Any, Match::Int(20)]) {
+            assert_eq!(1, res.len());
+        } else {
+            assert!(false);
+        }
+        if let Ok(None) = w.find(&vec![Match::UInt(5), Match::Any]) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+    });
+}
+
+#[test]
+fn save_and_load() {
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use oxide::Match;
+
+    let n = "foo";
+    let mut c = oxide::Cache::new();
+    {
+        let mut bb = oxide::BucketBuilder::new(n);
+        bb = bb.add_column(oxide::ColumnBuilder::UInt.key());
+        bb = bb.add_column(oxide::ColumnBuilder::Str);
+        bb = bb.add_column(oxide::ColumnBuilder::OwnedStr.nullable().case_insensitive());
+        bb = bb.add_column(oxide::ColumnBuilder::Float.ordered());
+        bb = bb.add_column(oxide::ColumnBuilder::Text.stop_words(&["the"]));
+        bb = bb.add_column(oxide::ColumnBuilder::Tags);
+        c.new_bucket(bb).unwrap();
+    }
+    c.bucket_mut(n, |w| {
+        let mut w = w.unwrap();
+        w.insert(vals![1usize, "a", "x".to_owned(), 1.5, "the red fox", vec!["t1", "t2"]]).unwrap();
+        w.insert(vals![2usize, "b", None::<String>, -2.0, "the blue fox", vec![3usize]]).unwrap();
+        w.insert(vals![3usize, "a", "y".to_owned(), 7.0, "a red hen", Vec::<usize>::new()])
+         .unwrap();
+        w.delete(&vec![Match::UInt(3), Match::Any, Match::Any, Match::Any, Match::Any,
+                       Match::Any])
+         .unwrap();
+    });
+
+    let dir = TestDir::new("save_and_load");
+    for &indices in [true, false].iter() {
+        let path = dir.join(format!("save-{}.db", indices));
+        c.save_to(&path, indices).unwrap();
+        let mut buf = Vec::new();
+        let l = oxide::Cache::load_from(&path, &mut buf).unwrap();
+        assert_eq!(true, l.has_bucket(n));
+        l.bucket(n, |r| {
+            let r = r.unwrap();
+            let stats = r.stats();
+            assert_eq!(3, stats.inserts);
+            assert_eq!(1, stats.deletes);
+            assert_eq!(2, r.rows());
+            assert!(!stats.index_stats[1].case_folded);
+            assert!(stats.index_stats[2].case_folded);
+            let m = vec![Match::Any, Match::Any, Match::IgnoreCase("X"), Match::Any, Match::Any,
+                         Match::Any];
+            assert_eq!(1, r.find(&m).unwrap().unwrap().len());
+            let m = vec![Match::Any, Match::Str("a"), Match::Any, Match::Any, Match::Any,
+                         Match::Any];
+            if let Ok(Some(res)) = r.find(&m) {
+                assert_eq!(1, res.len());
+                let row = res.iter().next().unwrap();
+                assert_eq!("x", format!("{}", row[2]));
+                assert_eq!("[t1, t2]", format!("{}", row[5]));
+            } else {
+                assert!(false);
+            }
+            let m = vec![Match::Any, Match::Any, Match::Null, Match::Lt(oxide::Value::Float(0.0)),
+                         Match::ContainsWord("fox"), Match::HasAny(vals![3usize])];
+            if let Ok(Some(res)) = r.find(&m) {
+                assert_eq!(1, res.len());
+            } else {
+                assert!(false);
+            }
+            if let Ok(None) = r.find(&vec![Match::Any, Match::Any, Match::Any, Match::Any,
+                                           Match::ContainsWord("the"), Match::Any]) {
+                assert!(true);
+            } else {
+                assert!(false);
+            }
+            if let Ok(Some(row)) = r.get_by_key(&vals![2usize]) {
+                assert_eq!("b", format!("{}", row[1]));
+            } else {
+                assert!(false);
+            }
+            if let Ok(None) = r.get_by_key(&vals![3usize]) {
+                assert!(true);
+            } else {
+                assert!(false);
+            }
+        });
+    }
+
+    let path = dir.join("save-true.db");
+    let mut good = Vec::new();
+    File::open(&path).unwrap().read_to_end(&mut good).unwrap();
+    let write = |data: &[u8]| {
+        let p = dir.join("bad.db");
+        File::create(&p).unwrap().write_all(data).unwrap();
+        p
+    };
+    let mut buf = Vec::new();
+
+    let mut bad = good.clone();
+    let l = bad.len();
+    bad[l / 2] ^= 0xff;
+    if let Err(oxide::Error::ChecksumMismatch) = oxide::Cache::load_from(write(&bad), &mut buf) {
+        assert!(true);
+    } else {
+        assert!(false);
+    }
+    let mut bad = good.clone();
+    bad[4] = 9;
+    if let Err(oxide::Error::UnsupportedVersion(9)) =
+           oxide::Cache::load_from(write(&bad), &mut buf) {
+        assert!(true);
+    } else {
+        assert!(false);
+    }
+    if let Err(oxide::Error::Corrupt(_)) = oxide::Cache::load_from(write(&good[..l - 3]),
+                                                                    &mut buf) {
+        assert!(true);
+    } else {
+        assert!(false);
+    }
+    if let Err(oxide::Error::Io(_)) = oxide::Cache::load_from(dir.join("nope.db"),
+                                                               &mut buf) {
+        assert!(true);
+    } else {
+        assert!(false);
+    }
+}
+
+#[test]
+fn load_checks() {
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use oxide::{ColumnBuilder, Value};
+
+    let dir = TestDir::new("load_checks");
+
+    // the temporary file is named after the whole file name, a sibling is left alone
+    File::create(dir.join("c.tmp")).unwrap().write_all(b"keep").unwrap();
+    new_cache_with_bucket("foo").save_to(dir.join("c.db"), false).unwrap();
+    let mut kept = String::new();
+    File::open(dir.join("c.tmp")).unwrap().read_to_string(&mut kept).unwrap();
+    assert_eq!("keep", kept);
+    assert!(!dir.join("c.db.tmp").exists());
+
+    // saves the rows, swaps the bytes `from` for `to` in the body and fixes up the checksum
+    let patched = |c: &oxide::Cache, from: &[u8], to: &[u8]| {
+        let path = dir.join("patched.db");
+        c.save_to(&path, false).unwrap();
+        let mut data = Vec::new();
+        File::open(&path).unwrap().read_to_end(&mut data).unwrap();
+        let l = data.len();
+        let at = (16..l - 8 - from.len()).find(|&i| &data[i..i + from.len()] == from).unwrap();
+        data[at..at + to.len()].copy_from_slice(to);
+        let sum = data[16..l - 8].iter().fold(0xcbf29ce484222325u64, |h, &b| {
+            (h ^ b as u64).wrapping_mul(0x100000001b3)
+        });
+        for i in 0..8 {
+            data[l - 8 + i] = (sum >> (i * 8)) as u8;
+        }
+        File::create(&path).unwrap().write_all(&data).unwrap();
+        path
+    };
+    let corrupt = |path, why: &str| {
+        let mut buf = Vec::new();
+        match oxide::Cache::load_from(path, &mut buf) {
+            Err(oxide::Error::Corrupt(m)) => m == why,
+            _ => false,
+        }
+    };
+
+    let mut c = oxide::Cache::new();
+    {
+        let mut bb = oxide::BucketBuilder::new("foo");
+        bb = bb.add_column(ColumnBuilder::UInt.key());
+        bb = bb.add_column(ColumnBuilder::OwnedStr.unique().nullable());
+        bb = bb.add_column(ColumnBuilder::Tags);
+        c.new_bucket(bb).unwrap();
+    }
+    c.bucket_mut("foo", |w| {
+        let mut w = w.unwrap();
+        w.insert(vals![1usize, "aaa".to_owned(), vec![5usize]]).unwrap();
+        w.insert(vals![2usize, "aab".to_owned(), Vec::<usize>::new()]).unwrap();
+        w.insert(vec![Value::UInt(3), Value::Null, Value::Tags(vec![])]).unwrap();
+        w.insert(vec![Value::UInt(4), Value::Null, Value::Tags(vec![])]).unwrap();
+    });
+    let mut buf = Vec::new();
+    assert!(oxide::Cache::load_from(patched(&c, b"aab", b"aab"), &mut buf).is_ok());
+
+    // ids past one roaring container, and a container full enough to be a bitset
+    let mut many = oxide::Cache::new();
+    many.new_bucket(oxide::BucketBuilder::new("bar").add_column(ColumnBuilder::UInt.ordered()))
+        .unwrap();
+    many.bucket_mut("bar", |w| {
+        let mut w = w.unwrap();
+        for i in 0..70000usize {
+            w.insert(vals![i]).unwrap();
+        }
+        assert_eq!(5000, w.delete(&vec![oxide::Match::Lt(Value::UInt(5000))]).unwrap());
+    });
+    many.save_to(dir.join("many.db"), true).unwrap();
+    let mut buf = Vec::new();
+    let l = oxide::Cache::load_from(dir.join("many.db"), &mut buf).unwrap();
+    l.bucket("bar", |r| {
+        let r = r.unwrap();
+        assert_eq!(65000, r.rows());
+        if let Ok(Some(res)) = r.find(&vec![oxide::Match::Ge(Value::UInt(69990))]) {
+            assert_eq!(10, res.len());
+        } else {
+            assert!(false);
+        }
+        if let Ok(None) = r.find(&vec![oxide::Match::UInt(4999)]) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+    });
+
+    // two rows under one key, two rows with the same unique value
+    assert!(corrupt(patched(&c, &[0, 2, 0, 0, 0, 0, 0, 0, 0, 4], &[0, 1]), "duplicate key"));
+    assert!(corrupt(patched(&c, b"aab", b"aaa"), "duplicate value in a unique column"));
+    // tags holding tags
+    let nested = [6, 1, 0, 0, 0, 0, 0, 0, 0, 6];
+    assert!(corrupt(patched(&c, &nested[..9], &nested), "tags nested in tags"));
+}
+
+#[test]
+fn wal() {
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use oxide::{Match, Pattern, Value};
+
+    let dir = TestDir::new("wal");
+    let snap = dir.join("wal.db");
+    let log = dir.join("wal.log");
+
+    let n = "foo";
+    let check = |c: &oxide::Cache| {
+        assert_eq!(false, c.has_bucket("bar"));
+        c.bucket(n, |r| {
+            let r = r.unwrap();
+            assert_eq!(4, r.rows());
+            if let Ok(Some(row)) = r.get_by_key(&vals![1usize]) {
+                assert_eq!("one", format!("{}", row[1]));
+                assert_eq!("11", format!("{}", row[2]));
+            } else {
+                assert!(false);
+            }
+            if let Ok(Some(row)) = r.get_by_key(&vals![2usize]) {
+                assert_eq!("20", format!("{}", row[2]));
+            } else {
+                assert!(false);
+            }
+            for k in [3usize, 6].iter() {
+                if let Ok(None) = r.get_by_key(&vals![*k]) {
+                    assert!(true);
+                } else {
+                    assert!(false);
+                }
+            }
+            if let Ok(Some(res)) = r.find(&vec![Match::Any, Match::Str("x"), Match::Any]) {
+                assert_eq!(2, res.len());
+            } else {
+                assert!(false);
+            }
+        });
+    };
+
+    {
+        let mut buf = Vec::new();
+        let mut c = oxide::Cache::open(&snap, &log, oxide::FsyncPolicy::Always, &mut buf).unwrap();
+        {
+            let mut bb = oxide::BucketBuilder::new(n);
+            bb = bb.add_column(oxide::ColumnBuilder::UInt.key());
+            bb = bb.add_column(oxide::ColumnBuilder::Str);
+            bb = bb.add_column(oxide::ColumnBuilder::Int.ordered());
+            c.new_bucket(bb).unwrap();
+        }
+        c.new_bucket(oxide::BucketBuilder::new("bar").add_column(oxide::ColumnBuilder::UInt))
+         .unwrap();
+        c.bucket_mut(n, |w| {
+            let mut w = w.unwrap();
+            w.insert(vals![1usize, "x", 10isize]).unwrap();
+            w.insert(vals![2usize, "x", 20isize]).unwrap();
+            // ops the bucket turns down are not logged
+            let len = fs::metadata(&log).unwrap().len();
+            assert!(w.insert(vals![2usize, "y", 20isize]).is_err());
+            assert!(w.insert_unique(vals![9usize, "y"]).is_err());
+            assert!(w.upsert(vals![9usize, "y", 1usize]).is_err());
+            assert!(w.update(&vec![Match::UInt(1), Match::Any, Match::Any],
+                             vec![(0, Value::UInt(2))])
+                     .is_err());
+            assert_eq!(len, fs::metadata(&log).unwrap().len());
+            assert_eq!(true, w.insert_unique(vals![3usize, "y", 30isize]).unwrap());
+            assert_eq!(true, w.upsert(vals![4usize, "z", 40isize]).unwrap());
+            assert_eq!(false, w.upsert(vals![1usize, "one", 10isize]).unwrap());
+            assert_eq!(1,
+                       w.update(&vec![Match::UInt(1), Match::Any, Match::Any],
+                                vec![(2, Value::Int(11))])
+                        .unwrap());
+            let c3 = w.get_column_ref(0).unwrap();
+            assert_eq!(1, w.delete_pattern(&Pattern::new(&c3, &Value::UInt(3))).unwrap());
+            w.compact().unwrap();
+            w.transaction(|tx| {
+                try!(tx.insert(vals![5usize, "x", 50isize]));
+                tx.insert(vals![6usize, "x", 60isize])
+            })
+             .unwrap()
+             .unwrap();
+            let r: Result<(), oxide::Error> = w.transaction(|tx| {
+                try!(tx.delete(&vec![Match::UInt(5), Match::Any, Match::Any]));
+                tx.insert(vals![6usize, "x", 61isize])
+            })
+             .unwrap();
+            assert!(r.is_err());
+            assert_eq!(1, w.delete(&vec![Match::UInt(6), Match::Any, Match::Any]).unwrap());
+            assert_eq!(1, w.delete(&vec![Match::UInt(4), Match::Any, Match::Any]).unwrap());
+            w.insert(vals![7usize, "w", 70isize]).unwrap();
+        });
+        c.try_drop_bucket("bar").unwrap();
+        check(&c);
+    }
+
+    // a last record failing its checksum was torn and is dropped, here the drop of bar. one
+    // with records behind it means the log is damaged
+    let good = fs::read(&log).unwrap();
+    let bad = dir.join("bad.log");
+    let mut torn = good.clone();
+    let l = torn.len();
+    torn[l - 1] ^= 0xff;
+    fs::File::create(&bad).unwrap().write_all(&torn).unwrap();
+    {
+        let mut buf = Vec::new();
+        let c = oxide::Cache::open(dir.join("none.db"), &bad, oxide::FsyncPolicy::Never, &mut buf)
+                    .unwrap();
+        assert!(c.has_bucket("bar"));
+        // the torn record is cut off
+        assert_eq!(true, fs::metadata(&bad).unwrap().len() < l as u64);
+    }
+    let mut damaged = good.clone();
+    damaged[16] ^= 0xff;
+    fs::File::create(&bad).unwrap().write_all(&damaged).unwrap();
+    let none = dir.join("none.db");
+    match oxide::Cache::open(&none, &bad, oxide::FsyncPolicy::Never, &mut Vec::new()) {
+        Err(oxide::Error::Corrupt(_)) => assert!(true),
+        _ => assert!(false),
+    }
+    assert_eq!(damaged.len() as u64, fs::metadata(&bad).unwrap().len());
+
+    // replay from the log alone, a torn record at the end is dropped
+    OpenOptions::new().append(true).open(&log).unwrap().write_all(&[9, 0, 0]).unwrap();
+    {
+        let mut buf = Vec::new();
+        let c = oxide::Cache::open(&snap, &log, oxide::FsyncPolicy::Never, &mut buf).unwrap();
+        check(&c);
+        // a snapshot next to a log that still has everything in it
+        c.save_to(&snap, true).unwrap();
+    }
+    {
+        let mut buf = Vec::new();
+        let mut c = oxide::Cache::open(&snap, &log, oxide::FsyncPolicy::Every(2), &mut buf)
+                        .unwrap();
+        check(&c);
+        c.checkpoint(false).unwrap();
+        // only the header is left
+        assert_eq!(8, fs::metadata(&log).unwrap().len());
+        c.bucket_mut(n, |w| {
+            w.unwrap().insert(vals![8usize, "v", 80isize]).unwrap();
+        });
+    }
+    {
+        let mut buf = Vec::new();
+        let c = oxide::Cache::open(&snap, &log, oxide::FsyncPolicy::Always, &mut buf).unwrap();
+        c.bucket(n, |r| {
+            let r = r.unwrap();
+            assert_eq!(5, r.rows());
+            if let Ok(Some(row)) = r.get_by_key(&vals![8usize]) {
+                assert_eq!("v", format!("{}", row[1]));
+            } else {
+                assert!(false);
+            }
+        });
+    }
+
+    let mut c = oxide::Cache::new();
+    if let Err(oxide::Error::NoLog) = c.checkpoint(true) {
+        assert!(true);
+    } else {
+        assert!(false);
+    }
+
+    // a log needs its header
+    let snap = dir.join("old.db");
+    let log = dir.join("old.log");
+    new_cache_with_bucket(n).save_to(&snap, false).unwrap();
+    fs::File::create(&log).unwrap().write_all(&[0; 24]).unwrap();
+    if let Err(oxide::Error::Corrupt(_)) =
+           oxide::Cache::open(&snap, &log, oxide::FsyncPolicy::Always, &mut Vec::new()) {
+        assert!(true);
+    } else {
+        assert!(false);
+    }
+
+    fs::File::create(&log).unwrap().write_all(b"OXWL\x63\0\0\0").unwrap();
+    if let Err(oxide::Error::UnsupportedVersion(99)) =
+           oxide::Cache::open(&snap, &log, oxide::FsyncPolicy::Always, &mut Vec::new()) {
+        assert!(true);
+    } else {
+        assert!(false);
+    }
+}
+
+#[test]
+fn csv() {
+    use oxide::{CsvOptions, Match, Pattern};
+
+    let n = "foo";
+    let mut c = oxide::Cache::new();
+    {
+        let mut bb = oxide::BucketBuilder::new(n);
+        bb = bb.add_column(oxide::ColumnBuilder::UInt.key().named("id"));
+        bb = bb.add_column(oxide::ColumnBuilder::OwnedStr.named("name"));
+        bb = bb.add_column(oxide::ColumnBuilder::Float.nullable());
+        bb = bb.add_column(oxide::ColumnBuilder::Tags.named("tags"));
+        c.new_bucket(bb).unwrap();
+    }
+
+    c.bucket_mut(n, |w| {
+        let mut w = w.unwrap();
+        // headers name their columns, an unnamed one by its index
+        let data = "id;name;2;tags\r\n\
+                    1;\"Smith; \"\"Jo\"\"\";1.5;a|b\r\n\
+                    2;NA;NA;\r\n\
+                    \r\n\
+                    3;\"multi\nline\";-2;c\r\n";
+        let opts = CsvOptions::new().delimiter(b';').null("NA");
+        assert_eq!(3, w.import_csv(data.as_bytes(), &opts).unwrap());
+        if let Ok(Some(row)) = w.get_by_key(&vals![1usize]) {
+            assert_eq!("Smith; \"Jo\"", format!("{}", row[1]));
+            assert_eq!("[a, b]", format!("{}", row[3]));
+        } else {
+            assert!(false);
+        }
+        if let Ok(Some(row)) = w.get_by_key(&vals![2usize]) {
+            // only nullable columns read the marker as null
+            assert_eq!("NA", format!("{}", row[1]));
+            assert_eq!("null", format!("{}", row[2]));
+            assert_eq!("[]", format!("{}", row[3]));
+        } else {
+            assert!(false);
+        }
+
+        // the bad record stops the import, nothing of it is kept
+        let data = "4,d,1,\n5,e,x,\n";
+        let opts = CsvOptions::new().headers(false);
+        if let Err(oxide::Error::AtLine(2, e)) = w.import_csv(data.as_bytes(), &opts) {
+            if let oxide::Error::WrongValueType(2) = *e {
+                assert!(true);
+            } else {
+                assert!(false);
+            }
+        } else {
+            assert!(false);
+        }
+        let data = "6,f,1,\n1,g,2,\n";
+        if let Err(oxide::Error::AtLine(2, e)) = w.import_csv(data.as_bytes(), &opts) {
+            if let oxide::Error::DuplicateKey(_) = *e {
+                assert!(true);
+            } else {
+                assert!(false);
+            }
+        } else {
+            assert!(false);
+        }
+        if let Err(oxide::Error::AtLine(1, _)) = w.import_csv("7,\"h,1,\n".as_bytes(), &opts) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+        if let Err(oxide::Error::AtLine(1, e)) = w.import_csv("7,h\n".as_bytes(), &opts) {
+            if let oxide::Error::WrongNumberOfValues(4, 2) = *e {
+                assert!(true);
+            } else {
+                assert!(false);
+            }
+        } else {
+            assert!(false);
+        }
+        assert_eq!(3, w.rows());
+
+        // headers picked by name, the rest left null
+        let data = "tags,score,key,name\nx|y,,8,h\n";
+        let opts = CsvOptions::new().map("key", 0).map("name", 1).map("tags", 3);
+        assert_eq!(1, w.import_csv(data.as_bytes(), &opts).unwrap());
+        if let Ok(Some(row)) = w.get_by_key(&vals![8usize]) {
+            assert_eq!("h", format!("{}", row[1]));
+            assert_eq!("null", format!("{}", row[2]));
+        } else {
+            assert!(false);
+        }
+        let opts = CsvOptions::new().map("nope", 0);
+        if let Err(oxide::Error::AtLine(1, _)) = w.import_csv(data.as_bytes(), &opts) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+        // headers naming no column or one column twice
+        for &(data, bad) in [("id,name,nope\n9,i,\n", "nope"), ("id,name,2,tags,\n9,i,,,\n", "")]
+                                .iter() {
+            match w.import_csv(data.as_bytes(), &CsvOptions::new()) {
+                Err(oxide::Error::AtLine(1, e)) => {
+                    if let oxide::Error::NoColumnNamed(ref h) = *e {
+                        assert_eq!(bad, h);
+                    } else {
+                        assert!(false);
+                    }
+                }
+                _ => assert!(false),
+            }
+        }
+        match w.import_csv("id,tags,id\n9,,9\n".as_bytes(), &CsvOptions::new()) {
+            Err(oxide::Error::AtLine(1, e)) => {
+                if let oxide::Error::RepeatedColumn(ref h) = *e {
+                    assert_eq!("id", h);
+                } else {
+                    assert!(false);
+                }
+            }
+            _ => assert!(false),
+        }
+        // columns without a header are left null
+        if let Err(oxide::Error::AtLine(2, e)) = w.import_csv("id,tags\n9,\n".as_bytes(),
+                                                                &CsvOptions::new()) {
+            if let oxide::Error::NotNullable(1) = *e {
+                assert!(true);
+            } else {
+                assert!(false);
+            }
+        } else {
+            assert!(false);
+        }
+        let opts = CsvOptions::new().headers(false).map("key", 0);
+        if let Err(oxide::Error::InvalidCsvOptions(_)) = w.import_csv(data.as_bytes(), &opts) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+        let opts = CsvOptions::new().delimiter(0xc3);
+        if let Err(oxide::Error::InvalidCsvOptions(_)) = w.import_csv("é\n".as_bytes(), &opts) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+        let opts = CsvOptions::new().quote(0xa9);
+        if let Err(oxide::Error::InvalidCsvOptions(_)) = w.export_csv(Vec::new(), &opts, None) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+        assert_eq!(4, w.rows());
+
+        let mut out = Vec::new();
+        let opts = CsvOptions::new().map("id", 0).null("NA");
+        assert_eq!(4, w.export_csv(&mut out, &opts, None).unwrap());
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!("id,name,2,tags\n\
+                    1,\"Smith; \"\"Jo\"\"\",1.5,a|b\n\
+                    2,\"NA\",NA,\n\
+                    3,\"multi\nline\",-2,c\n\
+                    8,h,NA,x|y\n",
+                   text);
+
+        let c0 = w.get_column_ref(0).unwrap();
+        let p = Pattern::ge(&c0, 3usize);
+        let mut out = Vec::new();
+        let opts = CsvOptions::new().headers(false);
+        assert_eq!(2, w.export_csv(&mut out, &opts, Some(&p)).unwrap());
+        assert_eq!("3,\"multi\nline\",-2,c\n8,h,,x|y\n",
+                   String::from_utf8(out).unwrap());
+
+        // what export writes, import reads back the same
+        let mut out = Vec::new();
+        let opts = CsvOptions::new().null("NA");
+        w.export_csv(&mut out, &opts, None).unwrap();
+        w.delete(&vec![Match::NotNull, Match::Any, Match::Any, Match::Any]).unwrap();
+        assert_eq!(4, w.import_csv(&out[..], &opts).unwrap());
+        if let Ok(Some(res)) = w.find(&vec![Match::Any, Match::OwnedStr("NA".to_owned()),
+                                            Match::Null, Match::Any]) {
+            assert_eq!(1, res.len());
+        } else {
+            assert!(false);
+        }
+
+        // headers in another order go to the columns they name, and read back the same
+        w.delete(&vec![Match::NotNull, Match::Any, Match::Any, Match::Any]).unwrap();
+        let data = "tags,2,name,id\nx|y,NA,h,8\na|b,1.5,Smith,1\n";
+        assert_eq!(2, w.import_csv(data.as_bytes(), &opts).unwrap());
+        let mut out = Vec::new();
+        w.export_csv(&mut out, &opts, None).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!("id,name,2,tags\n8,h,NA,x|y\n1,Smith,1.5,a|b\n", text);
+        w.delete(&vec![Match::NotNull, Match::Any, Match::Any, Match::Any]).unwrap();
+        assert_eq!(2, w.import_csv(text.as_bytes(), &opts).unwrap());
+        let mut out = Vec::new();
+        w.export_csv(&mut out, &opts, None).unwrap();
+        assert_eq!(text, String::from_utf8(out).unwrap());
+    });
+
+    // tags keep their type, strings that read as numbers are quoted
+    let m = "tags";
+    let bb = oxide::BucketBuilder::new(m)
+                 .add_column(oxide::ColumnBuilder::UInt.key().named("id"))
+                 .add_column(oxide::ColumnBuilder::Tags.named("tags"));
+    c.new_bucket(bb).unwrap();
+    c.bucket_mut(m, |w| {
+        use oxide::Value;
+        let mut w = w.unwrap();
+        w.insert(vec![Value::UInt(1),
+                      Value::Tags(vec![Value::UInt(7), Value::Str("7"), Value::Int(-2),
+                                       Value::Str("a|b"), Value::Str("\"q"), Value::Str("x")])])
+         .unwrap();
+        w.insert(vec![Value::UInt(2), Value::Tags(vec![Value::Str("")])]).unwrap();
+        let mut out = Vec::new();
+        let opts = CsvOptions::new();
+        w.export_csv(&mut out, &opts, None).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!("id,tags\n\
+                    1,\"7|\"\"7\"\"|-2|\"\"a|b\"\"|\"\"\"\"\"\"q\"\"|x\"\n\
+                    2,\"\"\"\"\"\"\n",
+                   text);
+        w.delete(&vec![Match::NotNull, Match::Any]).unwrap();
+        w.compact().unwrap();
+        assert_eq!(2, w.import_csv(text.as_bytes(), &opts).unwrap());
+        if let Ok(Some(row)) = w.get_by_key(&vals![1usize]) {
+            assert_eq!("[7, 7, -2, a|b, \"q, x]", format!("{}", row[1]));
+            match row[1] {
+                Value::Tags(ref ts) => {
+                    match (&ts[0], &ts[1], &ts[2]) {
+                        (&Value::UInt(7), &Value::OwnedStr(ref s), &Value::Int(-2)) => {
+                            assert_eq!("7", s)
+                        }
+                        _ => assert!(false),
+                    }
+                }
+                _ => assert!(false),
+            }
+        } else {
+            assert!(false);
+        }
+        for t in vec![Value::UInt(7), Value::Str("7"), Value::Int(-2), Value::Str("a|b")] {
+            if let Ok(Some(res)) = w.find(&vec![Match::Any, Match::HasAny(vec![t])]) {
+                assert_eq!(1, res.len());
+            } else {
+                assert!(false);
+            }
+        }
+        if let Ok(Some(res)) = w.find(&vec![Match::Any, Match::HasAny(vals![""])]) {
+            assert_eq!(1, res.len());
+        } else {
+            assert!(false);
+        }
+    });
+
+    // a Str column borrows nothing from the input, it gets shared strings
+    let m = "bar";
+    c.new_bucket(oxide::BucketBuilder::new(m).add_column(oxide::ColumnBuilder::Str)).unwrap();
+    c.bucket_mut(m, |w| {
+        let mut w = w.unwrap();
+        let opts = CsvOptions::new().headers(false);
+        assert_eq!(3, w.import_csv("a\nb\na\n".as_bytes(), &opts).unwrap());
+        if let Ok(Some(res)) = w.find(&vec![Match::Str("a")]) {
+            assert_eq!(2, res.len());
+            match res.iter().next().unwrap()[0] {
+                oxide::Value::Shared(ref s) => assert_eq!("a", &s[..]),
+                _ => assert!(false),
+            }
+        } else {
+            assert!(false);
+        }
+    });
+}
+
+#[test]
+fn json() {
+    use oxide::Match;
+
+    let n = "foo";
+    let mut c = oxide::Cache::new();
+    {
+        let mut bb = oxide::BucketBuilder::new(n);
+        bb = bb.add_column(oxide::ColumnBuilder::UInt.named("id").key());
+        bb = bb.add_column(oxide::ColumnBuilder::OwnedStr.named("name"));
+        bb = bb.add_column(oxide::ColumnBuilder::Int.named("delta").nullable());
+        bb = bb.add_column(oxide::ColumnBuilder::Float.named("score").nullable());
+        bb = bb.add_column(oxide::ColumnBuilder::Tags.named("tags"));
+        c.new_bucket(bb).unwrap();
+    }
+
+    c.bucket_mut(n, |w| {
+        let mut w = w.unwrap();
+        let data = "{\"id\": 1, \"name\": \"Jo \\\"J\\\" \\u00e9\\ud83d\\ude00\", \"delta\": -3, \
+                    \"score\": 1.5e1, \"tags\": [\"a\", 7]}\n\
+                    \n\
+                    {\"tags\": [], \"name\": \"x\", \"id\": 2, \"score\": 2}\n";
+        assert_eq!(2, w.import_ndjson(data.as_bytes()).unwrap());
+        if let Ok(Some(row)) = w.get_by_key(&vals![1usize]) {
+            assert_eq!("Jo \"J\" \u{e9}\u{1f600}", format!("{}", row[1]));
+            assert_eq!("-3", format!("{}", row[2]));
+            assert_eq!("15", format!("{}", row[3]));
+        } else {
+            assert!(false);
+        }
+        if let Ok(Some(row)) = w.get_by_key(&vals![2usize]) {
+            assert_eq!("null", format!("{}", row[2]));
+        } else {
+            assert!(false);
+        }
+
+        // the bad line stops the import, nothing of it is kept
+        let data = "{\"id\": 3, \"name\": \"y\", \"tags\": []}\n{\"id\": -4, \"name\": \"z\", \"tags\": []}\n";
+        match w.import_ndjson(data.as_bytes()) {
+            Err(oxide::Error::AtLine(2, e)) => {
+                match *e {
+                    oxide::Error::WrongJsonType { column: 0, found, .. } => {
+                        assert_eq!("a negative number", found)
+                    }
+                    _ => assert!(false),
+                }
+            }
+            _ => assert!(false),
+        }
+        match w.import_ndjson("{\"id\": 3, \"nope\": 1}\n".as_bytes()) {
+            Err(oxide::Error::AtLine(1, e)) => {
+                if let oxide::Error::NoColumnNamed(ref k) = *e {
+                    assert_eq!("nope", k);
+                } else {
+                    assert!(false);
+                }
+            }
+            _ => assert!(false),
+        }
+        match w.import_ndjson("{\"id\": 3, \"name\": \"y\"}\n".as_bytes()) {
+            Err(oxide::Error::AtLine(1, e)) => {
+                if let oxide::Error::NotNullable(4) = *e {
+                    assert!(true);
+                } else {
+                    assert!(false);
+                }
+            }
+            _ => assert!(false),
+        }
+        for bad in vec!["[1]", "{\"id\": 3", "{\"id\": 01}", "{\"name\": \"\\ud83d\"}",
+                        "{\"id\": 3, \"id\": 4, \"name\": \"y\", \"tags\": []}"] {
+            if let Err(oxide::Error::AtLine(1, _)) = w.import_ndjson(bad.as_bytes()) {
+                assert!(true);
+            } else {
+                assert!(false);
+            }
+        }
+        assert_eq!(2, w.rows());
+
+        let res = w.find(&vec![Match::UInt(2), Match::Any, Match::Any, Match::Any, Match::Any])
+                   .unwrap()
+                   .unwrap();
+        assert_eq!("[{\"id\":2,\"name\":\"x\",\"delta\":null,\"score\":2.0,\"tags\":[]}]",
+                   w.results_json(&res));
+
+        // what export writes, import reads back the same
+        let mut out = Vec::new();
+        assert_eq!(2, w.export_ndjson(&mut out, None).unwrap());
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!("{\"id\":1,\"name\":\"Jo \\\"J\\\" \u{e9}\u{1f600}\",\"delta\":-3,\"score\":15.0,\"tags\":[\"a\",7]}",
+                   text.lines().next().unwrap());
+        w.delete(&vec![Match::NotNull, Match::Any, Match::Any, Match::Any, Match::Any]).unwrap();
+        assert_eq!(2, w.import_ndjson(text.as_bytes()).unwrap());
+        if let Ok(Some(row)) = w.get_by_key(&vals![1usize]) {
+            assert_eq!("[a, 7]", format!("{}", row[4]));
+            assert_eq!(text.lines().next().unwrap(), w.row_json(&row));
+        } else {
+            assert!(false);
+        }
+    });
+
+    // an Int tag comes back as a number json does not type, it still matches as an Int
+    let m = "ints";
+    let bb = oxide::BucketBuilder::new(m).add_column(oxide::ColumnBuilder::Tags.named("t"));
+    c.new_bucket(bb).unwrap();
+    c.bucket_mut(m, |w| {
+        use oxide::Value;
+        let mut w = w.unwrap();
+        w.insert(vec![Value::Tags(vec![Value::Int(5), Value::Int(-5), Value::Str("5")])]).unwrap();
+        let mut out = Vec::new();
+        w.export_ndjson(&mut out, None).unwrap();
+        assert_eq!("{\"t\":[5,-5,\"5\"]}\n", String::from_utf8(out.clone()).unwrap());
+        w.delete(&vec![Match::NotNull]).unwrap();
+        w.compact().unwrap();
+        assert_eq!(1, w.import_ndjson(&out[..]).unwrap());
+        for t in vec![Value::Int(5), Value::UInt(5), Value::Int(-5), Value::Str("5")] {
+            if let Ok(Some(res)) = w.find(&vec![Match::HasAll(vec![t])]) {
+                assert_eq!(1, res.len());
+            } else {
+                assert!(false);
+            }
+        }
+        assert!(w.find(&vec![Match::HasAny(vec![Value::Str("-5")])]).unwrap().is_none());
+    });
+
+    let m = "strs";
+    let bb = oxide::BucketBuilder::new(m).add_column(oxide::ColumnBuilder::Str.named("s"));
+    c.new_bucket(bb).unwrap();
+    c.bucket_mut(m, |w| {
+        let mut w = w.unwrap();
+        assert_eq!(2, w.import_ndjson("{\"s\": \"a\"}\n{\"s\": \"b\"}\n".as_bytes()).unwrap());
+        if let Ok(Some(res)) = w.find(&vec![Match::Str("b")]) {
+            match res.iter().next().unwrap()[0] {
+                oxide::Value::Shared(ref s) => assert_eq!("b", &s[..]),
+                _ => assert!(false),
+            }
+        } else {
+            assert!(false);
+        }
+    });
+
+    let bb = oxide::BucketBuilder::new("bar")
+                 .add_column(oxide::ColumnBuilder::UInt.named("a"))
+                 .add_column(oxide::ColumnBuilder::UInt.named("a"));
+    if let Err(oxide::Error::DuplicateColumnName(_)) = c.new_bucket(bb) {
+        assert!(true);
+    } else {
+        assert!(false);
+    }
+}
+
+#[test]
+fn named_columns() {
+    use oxide::{ColumnBuilder, Match, Pattern, Value};
+
+    let n = "foo";
+    let mut c = oxide::Cache::new();
+    {
+        let mut bb = oxide::BucketBuilder::new(n);
+        bb = bb.add_column(ColumnBuilder::UInt.named("id").key());
+        bb = bb.add_column(ColumnBuilder::Str.named("kind"));
+        bb = bb.add_column(ColumnBuilder::OwnedStr.nullable());
+        bb = bb.add_column(ColumnBuilder::Float.named("score").ordered());
+        c.new_bucket(bb).unwrap();
+    }
+
+    c.bucket_mut(n, |w| {
+        let mut w = w.unwrap();
+        w.insert_named(&[("kind", Value::Str("a")), ("id", Value::UInt(1)),
+                         ("score", Value::Float(1.5))])
+         .unwrap();
+        w.insert_named(&[("score", Value::Float(-2.0)), ("id", Value::UInt(2)),
+                         ("kind", Value::Str("b"))])
+         .unwrap();
+        if let Err(oxide::Error::NoColumnNamed(ref k)) =
+               w.insert_named(&[("id", Value::UInt(3)), ("nope", Value::Null)]) {
+            assert_eq!("nope", k);
+        } else {
+            assert!(false);
+        }
+        // an unnamed column has no name to go by, not even its index
+        if let Err(oxide::Error::NoColumnNamed(ref k)) =
+               w.insert_named(&[("id", Value::UInt(3)), ("2", Value::Null)]) {
+            assert_eq!("2", k);
+        } else {
+            assert!(false);
+        }
+        if let Err(oxide::Error::RepeatedColumn(_)) =
+               w.insert_named(&[("id", Value::UInt(3)), ("id", Value::UInt(4))]) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+        if let Err(oxide::Error::NotNullable(1)) = w.insert_named(&[("id", Value::UInt(3))]) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+        assert_eq!(2, w.rows());
+
+        let m = w.matches_by_name(&[("kind", Match::Str("a"))]).unwrap();
+        assert_eq!(1, w.update(&m, vec![(2, Value::OwnedStr("x".to_owned()))]).unwrap());
+        let m = w.matches_by_name(&[("kind", Match::Str("b"))]).unwrap();
+        assert_eq!(4, m.len());
+        assert_eq!(1, w.update(&m, vec![(2, Value::OwnedStr("y".to_owned()))]).unwrap());
+        if let Ok(Some(row)) = w.get_by_key(&vals![1usize]) {
+            assert_eq!("{\"id\":1,\"kind\":\"a\",\"score\":1.5}", w.row_json(&row));
+        } else {
+            assert!(false);
+        }
+    });
+
+    let dir = TestDir::new("named_columns");
+    let path = dir.join("named.db");
+    c.save_to(&path, false).unwrap();
+    let mut buf = Vec::new();
+    let l = oxide::Cache::load_from(&path, &mut buf).unwrap();
+    l.bucket(n, |r| {
+        let r = r.unwrap();
+        let names: Vec<Option<&str>> = r.schema().iter().map(|cs| cs.name.as_ref().map(|s| &s[..])).collect();
+        assert_eq!(vec![Some("id"), Some("kind"), None, Some("score")], names);
+        assert!(r.schema()[0].key);
+        assert!(r.schema()[3].ordered);
+        if let ColumnBuilder::Float = r.schema()[3].kind {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+
+        if let Ok(Some(res)) = r.find_named(&[("score", Match::Gt(Value::Float(0.0)))]) {
+            assert_eq!(1, res.len());
+            assert_eq!("x", format!("{}", res.iter().next().unwrap()[2]));
+        } else {
+            assert!(false);
+        }
+        if let Ok(Some(res)) = r.find_named(&[("kind", Match::Str("b"))]) {
+            assert_eq!("y", format!("{}", res.iter().next().unwrap()[2]));
+        } else {
+            assert!(false);
+        }
+        if let Err(oxide::Error::NoColumnNamed(_)) = r.find_named(&[("2", Match::Any)]) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+        if let Err(oxide::Error::NoColumnNamed(_)) = r.find_named(&[("Score", Match::Any)]) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+
+        let id = r.get_column_ref_by_name("id").unwrap();
+        assert_eq!(0, id.id);
+        let p = Pattern::ge(&id, 2usize);
+        if let Ok(Some(res)) = r.find_pattern(&p) {
+            assert_eq!(1, res.len());
+        } else {
+            assert!(false);
+        }
+        // no column is reachable by its index
+        assert!(r.get_column_ref_by_name("2").is_none());
+        assert!(r.get_column_ref_by_name("1").is_none());
+    });
+}
+
+#[derive(OxideRow, Debug, PartialEq)]
+struct Item<'a> {
+    #[oxide(key)]
+    id: u64,
+    kind: &'a str,
+    #[oxide(name = "label", unique)]
+    name: String,
+    #[oxide(ordered)]
+    score: Option<f64>,
+    #[oxide(text)]
+    about: String,
+    tags: Vec<String>,
+}
+
+#[derive(OxideRow)]
+struct Flag {
+    on: bool,
+}
+
+// fields an owned cache's string columns can be read into
+#[derive(OxideRow, Debug, PartialEq)]
+struct Fruit {
+    #[oxide(key)]
+    id: u64,
+    name: ::std::sync::Arc<str>,
+    email: Option<String>,
+}
+
+#[derive(OxideRow)]
+struct Label {
+    id: u64,
+    name: String,
+    email: Option<::std::sync::Arc<str>>,
+}
+
+#[test]
+fn derive_row() {
+    use oxide::{ColumnBuilder, Match, OxideRow};
+
+    let n = "items";
+    let mut c = oxide::Cache::new();
+    c.new_bucket(Item::schema(n).auto_compact(0.5)).unwrap();
+    c.bucket_mut(n, |w| {
+        let mut w = w.unwrap();
+        let a = Item {
+            id: 1,
+            kind: "tool",
+            name: "hammer".to_owned(),
+            score: Some(2.5),
+            about: "hits nails".to_owned(),
+            tags: vec!["heavy".to_owned()],
+        };
+        w.insert(a.into_values()).unwrap();
+        let b = Item {
+            id: 2,
+            kind: "tool",
+            name: "saw".to_owned(),
+            score: None,
+            about: "cuts wood".to_owned(),
+            tags: Vec::new(),
+        };
+        w.insert(b.into_values()).unwrap();
+        let clash = Item {
+            id: 3,
+            kind: "tool",
+            name: "saw".to_owned(),
+            score: None,
+            about: String::new(),
+            tags: Vec::new(),
+        };
+        if let Err(oxide::Error::UniqueViolation { column: 2, .. }) = w.insert(clash.into_values()) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+
+        let s = w.schema();
+        let names: Vec<&str> = s.iter().map(|cs| &cs.name.as_ref().unwrap()[..]).collect();
+        assert_eq!(vec!["id", "kind", "label", "score", "about", "tags"], names);
+        assert!(s[0].key && s[2].unique && s[3].ordered && s[3].nullable);
+        if let ColumnBuilder::Text = s[4].kind {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+
+        let res = w.find_named(&[("about", Match::ContainsWord("nails"))]).unwrap().unwrap();
+        let row = Item::from_values(res.iter().next().unwrap()).unwrap();
+        assert_eq!(Item {
+                       id: 1,
+                       kind: "tool",
+                       name: "hammer".to_owned(),
+                       score: Some(2.5),
+                       about: "hits nails".to_owned(),
+                       tags: vec!["heavy".to_owned()],
+                   },
+                   row);
+        let res = w.find_named(&[("score", Match::Null)]).unwrap().unwrap();
+        assert_eq!(None, Item::from_values(res.iter().next().unwrap()).unwrap().score);
+
+        if let Err(oxide::Error::WrongNumberOfValues(1, 6)) = Flag::from_values(res.iter().next().unwrap()) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+        if let Err(oxide::Error::WrongValueType(0)) = Flag::from_values(&vals![1usize]) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+    });
+}
+
+#[test]
+fn typed_bucket() {
+    use oxide::{Filter, OxideRow, TypedBucket};
+
+    let n = "items";
+    let mut c = oxide::Cache::new();
+    c.new_bucket(Item::schema(n)).unwrap();
+    let f = Item::fields();
+    c.bucket_mut(n, |w| {
+        let mut t = TypedBucket::<_, Item>::new(w.unwrap()).unwrap();
+        for (id, name, score) in vec![(1, "hammer", Some(2.5)), (2, "saw", None), (3, "drill", Some(9.0))] {
+            t.insert(Item {
+                 id: id,
+                 kind: if id == 3 { "power" } else { "hand" },
+                 name: name.to_owned(),
+                 score: score,
+                 about: format!("the {} tool", name),
+                 tags: vec![name.to_owned(), "tool".to_owned()],
+             })
+             .unwrap();
+        }
+        assert_eq!(3, t.rows());
+
+        let found = t.find(&Filter::new().eq(f.kind, "hand").gt(f.score, 1.0)).unwrap();
+        assert_eq!(1, found.len());
+        assert_eq!("hammer", found[0].name);
+        let found = t.find(&Filter::new().is_null(f.score)).unwrap();
+        assert_eq!(vec![2], found.iter().map(|i| i.id).collect::<Vec<u64>>());
+        let found = t.find(&Filter::new()
+                                .contains_word(f.about, "tool")
+                                .has_any(f.tags, vec!["saw".to_owned(), "drill".to_owned()])
+                                .ge(f.id, 3))
+                     .unwrap();
+        assert_eq!(vec![3], found.iter().map(|i| i.id).collect::<Vec<u64>>());
+        // conditions on one field all apply
+        let found = t.find(&Filter::new().ge(f.id, 2).le(f.id, 2)).unwrap();
+        assert_eq!(1, found.len());
+        assert!(t.find(&Filter::new().prefix(f.name, "x")).unwrap().is_empty());
+
+        assert_eq!(false,
+                   t.upsert(Item {
+                        id: 2,
+                        kind: "hand",
+                        name: "saw".to_owned(),
+                        score: Some(4.0),
+                        about: "a sharp saw".to_owned(),
+                        tags: Vec::new(),
+                    })
+                    .unwrap());
+        assert_eq!(1, t.delete(&Filter::new().eq(f.name, "hammer".to_owned())).unwrap());
+
+        let all: Vec<Item> = t.iter().map(|r| r.unwrap()).collect();
+        assert_eq!(vec![2, 3], all.iter().map(|i| i.id).collect::<Vec<u64>>());
+        assert_eq!(Some(4.0), all[0].score);
+        assert_eq!(2, t.find(&Filter::new()).unwrap().len());
+    });
+
+    c.bucket(n, |r| {
+        let t = TypedBucket::<_, Item>::new(r.unwrap()).unwrap();
+        let found = t.find(&Filter::new().one_of(f.kind, vec!["power", "none"])).unwrap();
+        assert_eq!("drill", found[0].name);
+    });
+    c.bucket(n, |r| {
+        if let Err(oxide::Error::SchemaMismatch(1)) = TypedBucket::<_, Flag>::new(r.unwrap()) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+    });
+
+    let m = "flags";
+    c.new_bucket(oxide::BucketBuilder::new(m).add_column(oxide::ColumnBuilder::UInt)).unwrap();
+    c.bucket_mut(m, |w| {
+        if let Err(oxide::Error::SchemaMismatch(0)) = TypedBucket::<_, Flag>::new(w.unwrap()) {
+            assert!(true);
+        } else {
+            assert!(false);
+        }
+    });
+}
+
+#[test]
+fn owned_cache() {
+    use std::sync::Arc;
+    use std::thread;
+    use oxide::{Filter, Match, OxideRow, TypedBucket, Value};
+
+    struct Holder {
+        c: oxide::Cache<'static>,
+    }
+
+    let n = "foo";
+    let mut c = oxide::Cache::new();
+    {
+        let mut bb = oxide::BucketBuilder::new(n);
+        bb = bb.add_column(oxide::ColumnBuilder::UInt.key());
+        bb = bb.add_column(oxide::ColumnBuilder::Str.ordered());
+        bb = bb.add_column(oxide::ColumnBuilder::OwnedStr.unique().nullable());
+        c.new_bucket(bb).unwrap();
+    }
+    c.bucket_mut(n, |w| {
+        let mut w = w.unwrap();
+        w.insert(vals![1usize, "apple", "a@x".to_owned()]).unwrap();
+        w.insert(vals![2usize, "avocado", None::<String>]).unwrap();
+        w.insert(vals![3usize, "apple", "c@x".to_owned()]).unwrap();
+    });
+    let dir = TestDir::new("owned_cache");
+    let path = dir.join("owned.db");
+    c.save_to(&path, true).unwrap();
+
+    // nothing borrowed is left, so the cache can be kept in a struct and sent to a thread
+    let h = Holder { c: oxide::Cache::load_owned(&path).unwrap() };
+    let h = thread::spawn(move || {
+            let mut h = h;
+            h.c.bucket_mut(n, |w| {
+                let mut w = w.unwrap();
+                let s: Arc<str> = Arc::from("apricot");
+                w.insert(vec![Value::UInt(4), Value::Shared(s.clone()), Value::Shared(s)])
+                 .unwrap();
+                if let Err(oxide::Error::UniqueViolation { column, .. }) =
+                       w.insert(vec![Value::UInt(5), Value::Str("plum"),
+                                     Value::Shared(Arc::from("a@x"))]) {
+                    assert_eq!(2, column);
+                } else {
+                    assert!(false);
+                }
+            });
+            h
+        })
+        .join()
+        .unwrap();
+
+    h.c.bucket(n, |r| {
+        let r = r.unwrap();
+        assert_eq!(4, r.rows());
+        if let Ok(Some(res)) = r.find(&vec![Match::Any, Match::Str("apple"), Match::Any]) {
+            assert_eq!(2, res.len());
+        } else {
+            assert!(false);
+        }
+        if let Ok(Some(res)) = r.find(&vec![Match::Any, Match::Prefix("ap"), Match::Any]) {
+            assert_eq!(3, res.len());
+        } else {
+            assert!(false);
+        }
+        let m = vec![Match::Any, Match::Any, Match::OwnedStr("apricot".to_owned())];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!("4", format!("{}", res.iter().next().unwrap()[0]));
+        } else {
+            assert!(false);
+        }
+        let m = vec![Match::Any, Match::Not(Value::Shared(Arc::from("apple"))), Match::Any];
+        if let Ok(Some(res)) = r.find(&m) {
+            assert_eq!(2, res.len());
+        } else {
+            assert!(false);
+        }
+    });
+
+    // shared strings are written out and read back as they are
+    h.c.save_to(&path, false).unwrap();
+    let l = oxide::Cache::load_owned(&path).unwrap();
+    l.bucket(n, |r| {
+        let r = r.unwrap();
+        if let Ok(Some(row)) = r.get_by_key(&vals![4usize]) {
+            assert_eq!("apricot", format!("{}", row[1]));
+            // the row still holds one string for both columns
+            match (&row[1], &row[2]) {
+                (&Value::Shared(ref a), &Value::Shared(ref b)) => assert!(Arc::ptr_eq(a, b)),
+                _ => assert!(false),
+            }
+            let fruit = Fruit::from_values(&row).unwrap();
+            assert_eq!("apricot", &fruit.name[..]);
+            assert_eq!(Some("apricot".to_owned()), fruit.email);
+        } else {
+            assert!(false);
+        }
+    });
+
+    // String and Arc<str> fields fit the Str column and go in as shared strings
+    let mut l = l;
+    l.bucket_mut(n, |w| {
+        let f = Fruit::fields();
+        let mut t = TypedBucket::<_, Fruit>::new(w.unwrap()).unwrap();
+        t.insert(Fruit {
+             id: 5,
+             name: Arc::from("plum"),
+             email: Some("p@x".to_owned()),
+         })
+         .unwrap();
+        let found = t.find(&Filter::new().eq(f.name, Arc::from("apple"))).unwrap();
+        assert_eq!(vec![1, 3], found.iter().map(|r| r.id).collect::<Vec<u64>>());
+        let found = t.find(&Filter::new().prefix(f.name, "pl")).unwrap();
+        assert_eq!(vec![5], found.iter().map(|r| r.id).collect::<Vec<u64>>());
+    });
+    l.bucket_mut(n, |w| {
+        let f = Label::fields();
+        let mut t = TypedBucket::<_, Label>::new(w.unwrap()).unwrap();
+        t.insert(Label {
+             id: 6,
+             name: "plum".to_owned(),
+             email: None,
+         })
+         .unwrap();
+        let found = t.find(&Filter::new().eq(f.name, "plum".to_owned())).unwrap();
+        assert_eq!(vec![5, 6], found.iter().map(|r| r.id).collect::<Vec<u64>>());
+        let found = t.find(&Filter::new().ne(f.name, "apple".to_owned())).unwrap();
+        assert_eq!(vec![2, 4, 5, 6], found.iter().map(|r| r.id).collect::<Vec<u64>>());
+        let found = t.find(&Filter::new().one_of(f.name, vec!["avocado".to_owned()])).unwrap();
+        assert_eq!(vec![2], found.iter().map(|r| r.id).collect::<Vec<u64>>());
+        let all: Vec<Label> = t.iter().map(|r| r.unwrap()).collect();
+        assert_eq!(6, all.len());
+        assert_eq!(Some("p@x"), all[4].email.as_ref().map(|e| &e[..]));
+    });
+    l.bucket(n, |r| {
+        let r = r.unwrap();
+        if let Ok(Some(row)) = r.get_by_key(&vals![6usize]) {
+            match row[1] {
+                Value::Shared(ref s) => assert_eq!("plum", &s[..]),
+                _ => assert!(false),
+            }
+        } else {
+            assert!(false);
+        }
+    });
+}
+
+#[test]
+fn dictionary() {
+    use oxide::{Match, Value};
+
+    let n = "foo";
+    let mut c = oxide::Cache::new();
+    {
+        let mut bb = oxide::BucketBuilder::new(n);
+        bb = bb.add_column(oxide::ColumnBuilder::UInt.key());
+        bb = bb.add_column(oxide::ColumnBuilder::OwnedStr.dictionary().nullable());
+        c.new_bucket(bb).unwrap();
+    }
+    let colors = ["red", "green", "blue"];
+    c.bucket_mut(n, |w| {
+        let mut w = w.unwrap();
+        for i in 0..300usize {
+            w.insert(vals![i, colors[i % 3].to_owned()]).unwrap();
+        }
+        w.insert(vals![300usize, Value::Null]).unwrap();
+    });
+
+    let check = |c: &oxide::Cache, greens: usize| {
+        c.bucket(n, |r| {
+            let r = r.unwrap();
+            let s = &r.stats().index_stats[1];
+            assert_eq!(3, s.cardinality);
+            assert_eq!(1, s.nulls);
+            // IgnoreCase walks the three strings instead of keeping lowercased copies
+            assert!(!s.case_folded);
+            // the strings with the counts of their Arcs
+            let arc = 2 * ::std::mem::size_of::<usize>();
+            assert_eq!(12 + 3 * arc, s.string_bytes);
+            // a row holds a 4 byte code where it would hold a value and its string
+            let cell = ::std::mem::size_of::<Value>() - 4;
+            assert_eq!(100 * (cell + 3) + greens * (cell + 5) + 100 * (cell + 4) - s.string_bytes,
+                       s.saved_bytes);
+            assert_eq!(0, r.stats().index_stats[0].string_bytes);
+
+            let m = vec![Match::Any, Match::OwnedStr("green".to_owned())];
+            if let Ok(Some(res)) = r.find(&m) {
+                assert_eq!(greens, res.len());
+                // every row holds the dictionary's one copy of the string
+                let mut rows = res.iter();
+                match (&rows.next().unwrap()[1], &rows.next().unwrap()[1]) {
+                    (&Value::Shared(ref a), &Value::Shared(ref b)) => {
+                        assert!(::std::sync::Arc::ptr_eq(a, b))
+                    }
+                    _ => assert!(false),
+                }
+            } else {
+                assert!(false);
+            }
+            if let Ok(Some(res)) = r.find(&vec![Match::Any, Match::Prefix("bl")]) {
+                assert_eq!(100, res.len());
+            } else {
+                assert!(false);
+            }
+            if let Ok(Some(res)) = r.find(&vec![Match::Any, Match::IgnoreCase("RED")]) {
+                assert_eq!(100, res.len());
+            } else {
+                assert!(false);
+            }
+            if let Ok(Some(row)) = r.get_by_key(&vals![4usize]) {
+                assert_eq!("green", format!("{}", row[1]));
+            } else {
+                assert!(false);
+            }
+        });
+    };
+    check(&c, 100);
+
+    let dir = TestDir::new("dictionary");
+    for &indices in [true, false].iter() {
+        let path = dir.join(format!("dictionary-{}.db", indices));
+        c.save_to(&path, indices).unwrap();
+        let mut buf = Vec::new();
+        check(&oxide::Cache::load_from(&path, &mut buf).unwrap(), 100);
+    }
+
+    c.bucket_mut(n, |w| {
+        let mut w = w.unwrap();
+        // a string no row holds any more leaves the dictionary
+        w.update(&vec![Match::Any, Match::OwnedStr("green".to_owned())],
+                 vec![(1, Value::OwnedStr("gray".to_owned()))])
+         .unwrap();
+        assert_eq!(3, w.stats().index_stats[1].cardinality);
+        assert_eq!(false, w.upsert(vals![1usize, "green".to_owned()]).unwrap());
+        assert_eq!(4, w.stats().index_stats[1].cardinality);
+        // a deleted row keeps its string until compaction
+        assert_eq!(1, w.delete(&vec![Match::UInt(1), Match::Any]).unwrap());
+        assert_eq!(4, w.stats().index_stats[1].cardinality);
+        w.compact().unwrap();
+        assert_eq!(3, w.stats().index_stats[1].cardinality);
+        w.update(&vec![Match::Any, Match::OwnedStr("gray".to_owned())],
+                 vec![(1, Value::OwnedStr("green".to_owned()))])
+         .unwrap();
+        w.insert(vals![1usize, "green".to_owned()]).unwrap();
+    });
+    check(&c, 100);
+
+    // rolled back writes leave the rows holding their old codes
+    c.bucket_mut(n, |w| {
+        let mut w = w.unwrap();
+        let res = w.transaction(|tx| {
+                       try!(tx.update(&vec![Match::Any, Match::OwnedStr("red".to_owned())],
+                                      vec![(1, Value::OwnedStr("pink".to_owned()))]));
+                       try!(tx.insert(vals![301usize, "pink".to_owned()]));
+                       tx.insert(vals![0usize, "red".to_owned()])
+                   })
+                   .unwrap();
+        assert!(res.is_err());
+    });
+    check(&c, 100);
+
+    // a case insensitive dictionary column pays for its lowercased strings out of the saving
+    let bb = oxide::BucketBuilder::new("folded")
+                 .add_column(oxide::ColumnBuilder::UInt.key())
+                 .add_column(oxide::ColumnBuilder::OwnedStr
+                                 .dictionary()
+                                 .nullable()
+                                 .case_insensitive());
+    c.new_bucket(bb).unwrap();
+    c.bucket_mut("folded", |w| {
+        let mut w = w.unwrap();
+        for i in 0..300usize {
+            w.insert(vals![i, colors[i % 3].to_owned()]).unwrap();
+        }
+        w.insert(vals![300usize, Value::Null]).unwrap();
+    });
+    let mut plain = 0;
+    c.bucket(n, |r| plain = r.unwrap().stats().index_stats[1].saved_bytes);
+    c.bucket("folded", |r| {
+        let r = r.unwrap();
+        let s = &r.stats().index_stats[1];
+        assert!(s.case_folded);
+        assert!(!r.stats().index_stats[0].case_folded);
+        let folded = 12 + 3 * ::std::mem::size_of::<String>();
+        assert_eq!(plain - folded, s.saved_bytes);
+        if let Ok(Some(res)) = r.find(&vec![Match::Any, Match::IgnoreCase("Green")]) {
+            assert_eq!(100, res.len());
+        } else {
+            assert!(false);
+        }
+    });
+
+    let bb = oxide::BucketBuilder::new("bar")
+                 .add_column(oxide::ColumnBuilder::UInt)
+                 .add_column(oxide::ColumnBuilder::Str.dictionary());
+    if let Err(oxide::Error::InvalidDictionaryColumn(1)) = c.new_bucket(bb) {
+        assert!(true);
+    } else {
+        assert!(false);
+    }
+}